@@ -1,3 +1,4 @@
+use hls_handler::{HlsConfig, HlsStats, Stats};
 use media::get_episodes;
 use reqwest::Client;
 use serde_json::Value;
@@ -5,18 +6,48 @@ use std::env;
 use std::env::args;
 use std::error::Error;
 use std::io;
+use std::io::Write;
 use std::sync::{
     Arc,
     atomic::{AtomicBool, Ordering},
 };
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::fs::File;
 use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio_stream::StreamExt;
 
 const TIME_OUT: u64 = 10;
+const CONCURRENCY: usize = 4; // Segments téléchargés en parallèle
+const CACHE_DIR: &str = "odieux_cache"; // Partagé avec le lecteur d'odieux
 const URL_VALIDEUR_OD: &str = "https://services.radio-canada.ca/media/validation/v2/?appCode=medianet&connectionType=hd&deviceType=ipad&idMedia={}&multibitrate=true&output=json&tech=hls&manifestVersion=2";
 const URL_VALIDEUR_LIVE: &str = "https://services.radio-canada.ca/media/validation/v2/?appCode=medianetlive&connectionType=hd&deviceType=ipad&idMedia=cbvx&multibitrate=true&output=json&tech=hls&manifestVersion=2";
 
+// CRC-32 (IEEE 802.3) cumulatif de ce qui a été écrit
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    !data.iter().fold(!crc, |crc, &b| {
+        (0..8).fold(crc ^ b as u32, |crc, _| (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg()))
+    })
+}
+
+// Segment n/total, octets obtenus et temps restant estimé
+fn print_progress(stats: &Stats, elapsed: Duration) {
+    let mo = stats.bytes as f64 / 1_000_000.0;
+    match stats.progress() {
+        Some(progress) if progress > 0.0 => {
+            let eta = elapsed.as_secs_f64() * (1.0 - progress) / progress;
+            print!(
+                "\rSegment {}/{} ({:.0}%) {mo:.1} Mo, reste {:.0} s   ",
+                stats.segment + 1,
+                stats.segments,
+                progress * 100.0,
+                eta
+            );
+        }
+        _ => print!("\r{:.0} s {mo:.1} Mo   ", stats.position.as_secs_f64()),
+    }
+    let _ = io::stdout().flush();
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let (url, titre) = if args().len() > 1 {
@@ -47,12 +78,18 @@ async fn main() -> Result<(), Box<dyn Error>> {
     };
     let client = Client::builder().timeout(Duration::from_secs(TIME_OUT)).build()?;
     let task = tokio::spawn(client.get(&url).send().await?.text());
-    let mut aac = env::temp_dir();
-    aac.set_file_name(&titre);
-    aac.set_extension("aac");
-    let mut file = BufWriter::new(File::create(aac).await?);
+    let mut path = env::temp_dir();
+    path.set_file_name(&titre);
     let value: Value = serde_json::from_str(&task.await??)?;
-    let rx = hls_handler::start(value["url"].as_str().unwrap_or_default())?;
+    let stats = HlsStats::default();
+    let config = HlsConfig::builder().concurrency(CONCURRENCY).stats(stats.clone());
+    // Le cache de l'utilisateur, s'il en a un
+    let config = match dirs::cache_dir() {
+        Some(dir) => config.cache_dir(dir.join(CACHE_DIR)),
+        None => config,
+    }
+    .build()?;
+    let mut stream = hls_handler::stream(value["url"].as_str().unwrap_or_default(), config)?;
 
     let signal = Arc::new(AtomicBool::new(false));
     let signal2 = signal.clone();
@@ -63,16 +100,44 @@ async fn main() -> Result<(), Box<dyn Error>> {
         signal2.store(true, Ordering::Relaxed);
     });
 
-    for message in rx {
-        match message {
-            Ok(stream) => file.write_all(&stream).await?,
+    let mut file: Option<BufWriter<File>> = None;
+    let mut crc = 0;
+    let mut frames = 0;
+    let mut duration = Duration::ZERO;
+    let start = Instant::now();
+    while let Some(message) = stream.next().await {
+        let segment = match message {
+            Ok(segment) => segment,
             Err(e) => return Err(e.into()),
         };
+        if let Some(adts) = segment.adts {
+            frames += adts.frames;
+            duration += adts.duration;
+        }
+        let data = segment.data;
+        let file = match &mut file {
+            Some(file) => file,
+            None => {
+                // Un flux fMP4 commence par la boîte ftyp de sa section d'initialisation
+                path.set_extension(if data.get(4..8) == Some(b"ftyp") { "m4a" } else { "aac" });
+                file.insert(BufWriter::new(File::create(&path).await?))
+            }
+        };
+        file.write_all(&data).await?;
+        crc = crc32(crc, &data);
+        print_progress(&stats.snapshot(), start.elapsed());
         if signal.load(Ordering::Relaxed) {
             break;
         }
     }
-    file.flush().await?;
+    if let Some(mut file) = file {
+        file.flush().await?;
+        println!();
+        println!("{}: CRC-32 {crc:08x}", path.display());
+        if frames > 0 {
+            println!("{frames} trames ADTS, {:.3} secondes", duration.as_secs_f64());
+        }
+    }
 
     Ok(())
 }
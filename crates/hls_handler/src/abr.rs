@@ -0,0 +1,191 @@
+use std::time::Duration;
+use url::Url;
+
+// Pour rester sur une variante, le débit estimé doit dépasser son «bitrate» de 20%.
+// Pour monter, il doit dépasser celui de la variante suivante de 50% pendant 3 segments
+const DOWN_MARGIN: f64 = 1.2;
+const UP_MARGIN: f64 = 1.5;
+const UP_SAMPLES: usize = 3;
+const ALPHA: f64 = 0.3;
+
+// Les variantes interchangeables en ordre croissant de «bitrate». La variante choisie par
+// VariantPolicy est le plafond: la commutation adaptative ne fait que descendre puis y remonter.
+// Les variantes de même «bitrate» sont redondantes (autre serveur, PATHWAY-ID): elles servent à la relève
+pub(crate) struct Ladder {
+    variants: Vec<(u64, Vec<Url>)>,
+    current: usize,
+    pathway: usize, // L'url de chaque variante est choisi parmi ses redondantes
+    failovers: usize,
+    adaptive: bool,
+    estimate: Option<f64>, // Débit estimé en bits par seconde (moyenne mobile exponentielle)
+    up: usize,
+}
+
+impl Ladder {
+    pub(crate) fn new(variants: Vec<(u64, Url)>, adaptive: bool) -> Self {
+        let mut rungs: Vec<(u64, Vec<Url>)> = Vec::new();
+        for (bandwidth, url) in variants {
+            match rungs.last_mut() {
+                Some((last, urls)) if *last == bandwidth => urls.push(url),
+                _ => rungs.push((bandwidth, vec![url])),
+            }
+        }
+        Self {
+            current: rungs.len().saturating_sub(1),
+            variants: rungs,
+            pathway: 0,
+            failovers: 0,
+            adaptive,
+            estimate: None,
+            up: 0,
+        }
+    }
+
+    pub(crate) fn url(&self) -> &Url {
+        let urls = &self.variants[self.current].1;
+        &urls[self.pathway % urls.len()]
+    }
+
+    // Passer à la variante redondante suivante. Faux si elles ont toutes échoué depuis le dernier segment obtenu
+    pub(crate) fn failover(&mut self) -> bool {
+        if self.failovers + 1 >= self.variants[self.current].1.len() {
+            return false;
+        }
+        self.failovers += 1;
+        self.pathway += 1;
+        eprintln!("Relève par {}", self.url());
+        true
+    }
+
+    // Un segment a été transmis, qu'il soit téléchargé, du cache ou EXT-X-GAP: les redondantes sont de nouveau disponibles
+    pub(crate) fn delivered(&mut self) {
+        self.failovers = 0;
+    }
+
+    pub(crate) fn bandwidth(&self) -> u64 {
+        self.variants[self.current].0
+    }
+
+    // Mesure le débit d'un segment obtenu. Retourne vrai si la variante change pour le prochain segment
+    pub(crate) fn sample(&mut self, bytes: usize, elapsed: Duration) -> bool {
+        let bps = bytes as f64 * 8.0 / elapsed.as_secs_f64().max(0.001);
+        let estimate = match self.estimate {
+            Some(estimate) => ALPHA * bps + (1.0 - ALPHA) * estimate,
+            None => bps,
+        };
+        self.estimate = Some(estimate);
+        self.delivered();
+
+        if !self.adaptive {
+            return false;
+        }
+
+        // Un segment lent suffit pour descendre, mais il faut une moyenne soutenue pour monter
+        let current = self.current;
+        let sustainable = |bandwidth: u64| bandwidth as f64 * DOWN_MARGIN <= estimate.min(bps);
+        if current > 0 && !sustainable(self.variants[current].0) {
+            // Descendre sans attendre à la variante soutenable, sinon à la plus faible
+            self.current = self.variants[..current]
+                .iter()
+                .rposition(|(bandwidth, _)| sustainable(*bandwidth))
+                .unwrap_or(0);
+            self.up = 0;
+        } else if current + 1 < self.variants.len() && self.variants[current + 1].0 as f64 * UP_MARGIN <= estimate {
+            self.up += 1;
+            if self.up == UP_SAMPLES {
+                self.current += 1;
+                self.up = 0;
+            }
+        } else {
+            self.up = 0;
+        }
+
+        if self.current != current {
+            eprintln!("Variante de {} bps (débit estimé de {:.0} bps)", self.bandwidth(), estimate);
+        }
+        self.current != current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ladder(adaptive: bool) -> Ladder {
+        let variants = [48_000, 96_000, 192_000]
+            .into_iter()
+            .map(|bandwidth| (bandwidth, Url::parse(&format!("https://cdn/{bandwidth}.m3u8")).unwrap()))
+            .collect();
+        Ladder::new(variants, adaptive)
+    }
+
+    // Un segment de 10 secondes obtenu au débit donné
+    fn sample(ladder: &mut Ladder, bps: u64) -> bool {
+        ladder.sample(
+            (ladder.bandwidth() * 10 / 8) as usize,
+            Duration::from_secs_f64((ladder.bandwidth() * 10) as f64 / bps as f64),
+        )
+    }
+
+    #[test]
+    fn switching() {
+        let mut ladder = ladder(true);
+        assert_eq!(ladder.bandwidth(), 192_000);
+        assert!(!sample(&mut ladder, 1_000_000));
+
+        // Le réseau ralentit
+        assert!(sample(&mut ladder, 150_000));
+        assert_eq!(ladder.bandwidth(), 96_000);
+        assert!(sample(&mut ladder, 30_000));
+        assert_eq!(ladder.bandwidth(), 48_000);
+        assert!(!sample(&mut ladder, 30_000));
+        assert_eq!(ladder.url().as_str(), "https://cdn/48000.m3u8");
+
+        // Le réseau se rétablit
+        let mut switched = 0;
+        for _ in 0..30 {
+            if sample(&mut ladder, 1_000_000) {
+                switched += 1;
+            }
+        }
+        assert_eq!(switched, 2);
+        assert_eq!(ladder.bandwidth(), 192_000);
+    }
+
+    #[test]
+    fn failover() {
+        let variants = ["https://a/96.m3u8", "https://a/192.m3u8", "https://b/192.m3u8", "https://c/192.m3u8"]
+            .into_iter()
+            .zip([96_000, 192_000, 192_000, 192_000])
+            .map(|(url, bandwidth)| (bandwidth, Url::parse(url).unwrap()))
+            .collect();
+        let mut ladder = Ladder::new(variants, false);
+        assert_eq!(ladder.url().as_str(), "https://a/192.m3u8");
+        assert!(ladder.failover());
+        assert!(ladder.failover());
+        assert_eq!(ladder.url().as_str(), "https://c/192.m3u8");
+        // Toutes ont échoué
+        assert!(!ladder.failover());
+
+        // Un segment obtenu permet une nouvelle relève
+        sample(&mut ladder, 1_000_000);
+        assert!(ladder.failover());
+        assert_eq!(ladder.url().as_str(), "https://a/192.m3u8");
+
+        // Un segment transmis sans mesure du débit aussi
+        assert!(ladder.failover());
+        assert!(!ladder.failover());
+        ladder.delivered();
+        assert!(ladder.failover());
+
+        let mut single = self::ladder(false);
+        assert!(!single.failover());
+    }
+
+    #[test]
+    fn fixed() {
+        let mut ladder = ladder(false);
+        assert!(!sample(&mut ladder, 10_000));
+        assert_eq!(ladder.bandwidth(), 192_000);
+    }
+}
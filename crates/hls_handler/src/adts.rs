@@ -0,0 +1,262 @@
+use bytes::Bytes;
+use std::time::Duration;
+
+// Fréquences d'échantillonnage selon l'indice de l'entête ADTS
+const FREQUENCIES: [u32; 13] = [96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350];
+const SAMPLES_PER_FRAME: f64 = 1024.0;
+const LC: u8 = 1; // Profil AAC-LC (audio object type - 1)
+
+// Trames AAC-LC silencieuses, mono et stéréo (hls.js, silentframe.ts)
+const SILENT_MONO: [u8; 6] = [0x00, 0xc8, 0x00, 0x80, 0x23, 0x80];
+const SILENT_STEREO: [u8; 9] = [0x21, 0x00, 0x49, 0x90, 0x02, 0x19, 0x00, 0x23, 0x80];
+
+// La configuration audio d'une trame ADTS (ISO/IEC 13818-7)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdtsConfig {
+    profile: u8,
+    frequency: u8,
+    channels: u8,
+}
+
+impl AdtsConfig {
+    // L'entête de la première trame
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        match data {
+            [0xFF, b1, b2, b3, _, _, _, ..] if b1 & 0xF6 == 0xF0 && ((b2 >> 2) & 0x0F) < FREQUENCIES.len() as u8 => Some(Self {
+                profile: b2 >> 6,
+                frequency: (b2 >> 2) & 0x0F,
+                channels: ((b2 & 0x01) << 2) | (b3 >> 6),
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        FREQUENCIES[self.frequency as usize]
+    }
+
+    // 0: la configuration est dans le flux (PCE)
+    pub fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    // Durée d'une trame d'un seul bloc de données
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_secs_f64(SAMPLES_PER_FRAME / self.sample_rate() as f64)
+    }
+
+    fn header(&self, frame_len: usize) -> [u8; 7] {
+        [
+            0xFF,
+            0xF1, // MPEG-4, sans CRC
+            (self.profile << 6) | (self.frequency << 2) | (self.channels >> 2),
+            ((self.channels & 0x03) << 6) | ((frame_len >> 11) & 0x03) as u8,
+            (frame_len >> 3) as u8,
+            (((frame_len & 0x07) << 5) as u8) | 0x1F,
+            0xFC,
+        ]
+    }
+}
+
+// Des trames ADTS silencieuses couvrant la durée. Seuls les flux AAC-LC mono ou stéréo sont pris en charge
+pub(crate) fn silence(config: AdtsConfig, duration: Duration) -> Option<Vec<u8>> {
+    let frame: &[u8] = match (config.profile, config.channels) {
+        (LC, 1) => &SILENT_MONO,
+        (LC, 2) => &SILENT_STEREO,
+        _ => return None,
+    };
+    let frames = (duration.as_secs_f64() * config.sample_rate() as f64 / SAMPLES_PER_FRAME).round() as usize;
+
+    let header = config.header(7 + frame.len());
+    let mut data = Vec::with_capacity(frames * (7 + frame.len()));
+    for _ in 0..frames {
+        data.extend_from_slice(&header);
+        data.extend_from_slice(frame);
+    }
+    Some(data)
+}
+
+// Une trame ADTS complète, entête compris
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdtsFrame<'a> {
+    pub config: AdtsConfig,
+    pub data: &'a [u8],
+}
+
+impl AdtsFrame<'_> {
+    // Une trame peut contenir jusqu'à 4 blocs de 1024 échantillons
+    pub fn blocks(&self) -> u32 {
+        (self.data[6] as u32 & 0x03) + 1
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.config.frame_duration() * self.blocks()
+    }
+
+    fn parse(data: &[u8]) -> Option<AdtsFrame<'_>> {
+        let config = AdtsConfig::parse(data)?;
+        let header_len = if data[1] & 0x01 == 0 { 9 } else { 7 }; // Avec le CRC
+        let frame_len = ((data[3] as usize & 0x03) << 11) | ((data[4] as usize) << 3) | (data[5] as usize >> 5);
+        if frame_len < header_len {
+            return None;
+        }
+        let frame = data.get(..frame_len)?;
+        // La trame suivante, s'il y en a une, doit débuter par la synchronisation
+        match data.get(frame_len..frame_len + 2) {
+            Some(&[b0, b1]) if b0 != 0xFF || b1 & 0xF6 != 0xF0 => None,
+            _ => Some(AdtsFrame { config, data: frame }),
+        }
+    }
+}
+
+// Les trames valides d'un flux ADTS. Les octets corrompus sont sautés jusqu'à la prochaine synchronisation
+pub struct AdtsFrames<'a> {
+    data: &'a [u8],
+    pos: usize,
+    skipped: usize,
+}
+
+impl<'a> AdtsFrames<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0, skipped: 0 }
+    }
+
+    // Octets ignorés jusqu'ici
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+}
+
+impl<'a> Iterator for AdtsFrames<'a> {
+    type Item = AdtsFrame<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.pos < self.data.len() {
+            match AdtsFrame::parse(&self.data[self.pos..]) {
+                Some(frame) => {
+                    self.pos += frame.data.len();
+                    return Some(frame);
+                }
+                None => {
+                    self.pos += 1;
+                    self.skipped += 1;
+                }
+            }
+        }
+        None
+    }
+}
+
+// Le décompte des trames valides d'un segment
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdtsSummary {
+    pub config: AdtsConfig,
+    pub frames: usize,
+    pub duration: Duration, // Durée exacte de l'audio
+    pub skipped: usize,
+}
+
+// Ne garder que les trames valides. Le flux est retourné intact s'il ne contient aucune trame, et n'est
+// copié que si des octets corrompus séparent des trames
+pub(crate) fn validate(data: Bytes) -> (Bytes, Option<AdtsSummary>) {
+    let mut frames = AdtsFrames::new(&data);
+    let Some(first) = frames.next() else {
+        return (data, None);
+    };
+    let mut summary = AdtsSummary {
+        config: first.config,
+        frames: 1,
+        duration: first.duration(),
+        skipped: 0,
+    };
+    // Les suites de trames contiguës, en positions dans le flux
+    let position = |frame: &AdtsFrame| {
+        let start = frame.data.as_ptr() as usize - data.as_ptr() as usize;
+        start..start + frame.data.len()
+    };
+    let mut runs = vec![position(&first)];
+    for frame in frames.by_ref() {
+        let range = position(&frame);
+        match runs.last_mut() {
+            Some(run) if run.end == range.start => run.end = range.end,
+            _ => runs.push(range),
+        }
+        summary.frames += 1;
+        summary.duration += frame.duration();
+    }
+    summary.skipped = frames.skipped();
+
+    let valid = match runs.as_slice() {
+        [run] => data.slice(run.clone()),
+        runs => {
+            let mut valid = Vec::with_capacity(runs.iter().map(|run| run.len()).sum());
+            runs.iter().for_each(|run| valid.extend_from_slice(&data[run.clone()]));
+            valid.into()
+        }
+    };
+    (valid, Some(summary))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn silence_frames() {
+        // AAC-LC, 44,1 kHz, stéréo
+        let config = AdtsConfig::parse(&[0xFF, 0xF1, 0x50, 0x80, 0x2E, 0x7F, 0xFC]).unwrap();
+        assert_eq!(
+            config,
+            AdtsConfig {
+                profile: 1,
+                frequency: 4,
+                channels: 2
+            }
+        );
+
+        let data = silence(config, Duration::from_secs(2)).unwrap();
+        assert_eq!(data.len(), 86 * 16);
+        assert_eq!(AdtsConfig::parse(&data), Some(config));
+        assert_eq!(&data[..7], &[0xFF, 0xF1, 0x50, 0x80, 0x02, 0x1F, 0xFC]);
+
+        let he = AdtsConfig { profile: 2, ..config };
+        assert!(silence(he, Duration::from_secs(2)).is_none());
+        assert!(AdtsConfig::parse(&[0x47, 0x40, 0x00]).is_none());
+    }
+
+    #[test]
+    fn frames() {
+        let config = AdtsConfig::parse(&[0xFF, 0xF1, 0x50, 0x80, 0x2E, 0x7F, 0xFC]).unwrap();
+        assert_eq!((config.sample_rate(), config.channels()), (44100, 2));
+        let silence = silence(config, Duration::from_millis(70)).unwrap();
+        assert_eq!(silence.len(), 3 * 16);
+
+        // Des octets corrompus au début, au milieu, et une trame tronquée à la fin
+        let mut data = b"ID3garbage".to_vec();
+        data.extend_from_slice(&silence[..32]);
+        data.extend_from_slice(&[0xFF, 0xF1, 0x00]);
+        data.extend_from_slice(&silence[32..]);
+        data.extend_from_slice(&silence[..10]);
+
+        let frames = AdtsFrames::new(&data).collect::<Vec<_>>();
+        assert_eq!(frames.len(), 3);
+        assert!(frames.iter().all(|frame| frame.data.len() == 16 && frame.blocks() == 1));
+
+        let (valid, summary) = validate(data.into());
+        assert_eq!(valid, silence);
+        let summary = summary.unwrap();
+        assert_eq!((summary.frames, summary.skipped), (3, 10 + 3 + 10));
+        assert_eq!(summary.duration, config.frame_duration() * 3);
+
+        // Des octets corrompus au début seulement: le flux n'est pas copié
+        let data = Bytes::from([b"ID3garbage".as_slice(), &silence].concat());
+        let (valid, summary) = validate(data.clone());
+        assert_eq!(valid, silence);
+        assert_eq!(valid.as_ptr(), data[10..].as_ptr());
+        assert_eq!(summary.unwrap().skipped, 10);
+
+        // Un flux sans trame est laissé intact
+        let mp3 = Bytes::from_static(b"mp3");
+        assert_eq!(validate(mp3.clone()), (mp3, None));
+    }
+}
@@ -0,0 +1,116 @@
+use crate::HlsError;
+use libaes::Cipher;
+
+const BLOCK: usize = 16;
+
+// La clé AES-128 d'un segment et son IV
+#[derive(Clone, Debug)]
+pub(crate) struct SegmentKey {
+    key: [u8; BLOCK],
+    iv: [u8; BLOCK],
+}
+
+impl SegmentKey {
+    pub(crate) fn new(key: &[u8], iv: &[u8]) -> Result<Self, HlsError> {
+        Ok(Self {
+            key: key
+                .try_into()
+                .map_err(|_| HlsError::Key("La clé n'a pas une longueur de 16 octets".to_owned()))?,
+            iv: iv
+                .try_into()
+                .map_err(|_| HlsError::Key("L'IV n'a pas une longueur de 16 octets".to_owned()))?,
+        })
+    }
+}
+
+// Décryption AES-128-CBC au fil des morceaux reçus: chaque bloc ne dépend que du bloc chiffré qui le
+// précède. Le dernier bloc est retenu jusqu'à la fin pour en retirer le remplissage PKCS7
+pub(crate) struct CbcDecryptor {
+    cipher: Cipher,
+    iv: [u8; BLOCK],
+    pending: Vec<u8>,
+}
+
+impl CbcDecryptor {
+    pub(crate) fn new(key: &SegmentKey) -> Self {
+        let mut cipher = Cipher::new_128(&key.key);
+        cipher.set_auto_padding(false);
+        Self {
+            cipher,
+            iv: key.iv,
+            pending: Vec::with_capacity(BLOCK),
+        }
+    }
+
+    // Ajoute à out les blocs complets reçus, sauf le dernier
+    pub(crate) fn update(&mut self, mut chunk: &[u8], out: &mut Vec<u8>) {
+        // Le bloc en attente n'est décrypté que si d'autres octets le suivent
+        if !self.pending.is_empty() {
+            let fill = (BLOCK - self.pending.len()).min(chunk.len());
+            self.pending.extend_from_slice(&chunk[..fill]);
+            chunk = &chunk[fill..];
+            if chunk.is_empty() {
+                return;
+            }
+            let pending = std::mem::take(&mut self.pending);
+            self.decrypt(&pending, out);
+        }
+        let len = chunk.len().saturating_sub(1) / BLOCK * BLOCK;
+        self.decrypt(&chunk[..len], out);
+        self.pending.extend_from_slice(&chunk[len..]);
+    }
+
+    fn decrypt(&mut self, blocks: &[u8], out: &mut Vec<u8>) {
+        if blocks.is_empty() {
+            return;
+        }
+        out.extend_from_slice(&self.cipher.cbc_decrypt(&self.iv, blocks));
+        self.iv.copy_from_slice(&blocks[blocks.len() - BLOCK..]);
+    }
+
+    pub(crate) fn finish(self, out: &mut Vec<u8>) -> Result<(), HlsError> {
+        if self.pending.len() != BLOCK {
+            return Err(HlsError::Decrypt("La décryption a échoué".to_owned()));
+        }
+        let last = self.cipher.cbc_decrypt(&self.iv, &self.pending);
+        let padding = last[BLOCK - 1] as usize;
+        if !(1..=BLOCK).contains(&padding) || last[BLOCK - padding..].iter().any(|&b| b as usize != padding) {
+            return Err(HlsError::Decrypt("La décryption a échoué".to_owned()));
+        }
+        out.extend_from_slice(&last[..BLOCK - padding]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks() {
+        let key = SegmentKey::new(b"4567890123456789", b"1234567890123456").unwrap();
+        let cipher = Cipher::new_128(&key.key);
+        for len in [0, 5, 16, 100] {
+            let plain = (0..len).map(|b| b as u8).collect::<Vec<_>>();
+            let encrypted = cipher.cbc_encrypt(&key.iv, &plain);
+            // Des morceaux qui ne respectent pas les blocs
+            for size in [1, 7, 16, 33, 1000] {
+                let mut decryptor = CbcDecryptor::new(&key);
+                let mut out = Vec::new();
+                encrypted.chunks(size).for_each(|chunk| decryptor.update(chunk, &mut out));
+                decryptor.finish(&mut out).unwrap();
+                assert_eq!(out, plain, "{len} octets par morceaux de {size}");
+            }
+        }
+
+        // Contenu tronqué ou mauvaise clé
+        let encrypted = cipher.cbc_encrypt(&key.iv, b"DOH!");
+        let mut decryptor = CbcDecryptor::new(&key);
+        decryptor.update(&encrypted[..10], &mut Vec::new());
+        assert!(decryptor.finish(&mut Vec::new()).is_err());
+        let mut decryptor = CbcDecryptor::new(&SegmentKey::new(b"0000000000000000", &key.iv).unwrap());
+        decryptor.update(&encrypted, &mut Vec::new());
+        assert!(decryptor.finish(&mut Vec::new()).is_err());
+        assert!(SegmentKey::new(b"court", &key.iv).is_err());
+    }
+}
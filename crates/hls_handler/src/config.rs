@@ -0,0 +1,315 @@
+use anyhow::{Context, Result};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, Proxy};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use crate::disk_cache::DiskCache;
+use crate::rendition::RenditionPolicy;
+use crate::variant::{CODECS, VariantPolicy};
+use crate::{HlsStats, Metadata};
+
+const TIME_OUT: u64 = 30;
+const MAX_RETRIES: usize = 20; // Reprises après la première tentative, au délai exponentiel depuis retry_delay
+const RETRY_DELAY: u64 = 250;
+const BOUND: usize = 3;
+const CACHE_SIZE: u64 = 256 << 20;
+const CACHE_TTL: u64 = 7 * 24 * 3600;
+
+// Traitement des segments EXT-X-GAP, qui ne sont jamais obtenus
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum GapPolicy {
+    #[default]
+    Skip,
+    // Du silence de la durée du segment, pour un flux AAC-LC
+    Silence,
+}
+
+// Premier segment obtenu d'un flux «live»
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LiveStart {
+    // Le plus ancien de la playlist
+    #[default]
+    Oldest,
+    // N segments avant le plus récent
+    Edge(usize),
+    // HOLD-BACK de EXT-X-SERVER-CONTROL, sinon trois fois la durée cible
+    HoldBack,
+}
+
+#[derive(Clone, Debug)]
+pub struct HlsConfig {
+    pub(crate) client: Client,
+    pub(crate) max_retries: usize,
+    pub(crate) retry_delay: Duration,
+    pub(crate) bound: usize,
+    pub(crate) variant: VariantPolicy,
+    pub(crate) rendition: RenditionPolicy,
+    pub(crate) codecs: Vec<String>,
+    pub(crate) adaptive: bool,
+    pub(crate) concurrency: usize,
+    pub(crate) gap: GapPolicy,
+    pub(crate) metadata: Option<Sender<Metadata>>,
+    pub(crate) stats: HlsStats,
+    pub(crate) start_position: Duration,
+    pub(crate) live_start: LiveStart,
+    pub(crate) max_latency: Option<Duration>,
+    pub(crate) low_latency: bool,
+    pub(crate) cache: Option<DiskCache>,
+}
+
+impl HlsConfig {
+    pub fn builder() -> HlsConfigBuilder {
+        HlsConfigBuilder::default()
+    }
+}
+
+// Panique si le Client ne peut être créé: HlsConfig::builder().build() retourne plutôt l'erreur
+impl Default for HlsConfig {
+    fn default() -> Self {
+        HlsConfigBuilder::default().build().expect("Échec: création de HlsConfig")
+    }
+}
+
+pub struct HlsConfigBuilder {
+    timeout: Duration,
+    max_retries: usize,
+    retry_delay: Duration,
+    bound: usize,
+    user_agent: Option<String>,
+    headers: Vec<(String, String)>,
+    proxy: Option<String>,
+    client: Option<Client>,
+    variant: VariantPolicy,
+    rendition: RenditionPolicy,
+    codecs: Vec<String>,
+    adaptive: bool,
+    concurrency: usize,
+    gap: GapPolicy,
+    metadata: Option<Sender<Metadata>>,
+    stats: HlsStats,
+    start_position: Duration,
+    live_start: LiveStart,
+    max_latency: Option<Duration>,
+    low_latency: bool,
+    cache_dir: Option<PathBuf>,
+    cache_size: u64,
+    cache_ttl: Duration,
+}
+
+impl Default for HlsConfigBuilder {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(TIME_OUT),
+            max_retries: MAX_RETRIES,
+            retry_delay: Duration::from_millis(RETRY_DELAY),
+            bound: BOUND,
+            user_agent: None,
+            headers: Vec::new(),
+            proxy: None,
+            client: None,
+            variant: VariantPolicy::default(),
+            rendition: RenditionPolicy::default(),
+            codecs: CODECS.iter().map(|codec| codec.to_string()).collect(),
+            adaptive: false,
+            concurrency: 1,
+            gap: GapPolicy::default(),
+            metadata: None,
+            stats: HlsStats::default(),
+            start_position: Duration::ZERO,
+            live_start: LiveStart::default(),
+            max_latency: None,
+            low_latency: false,
+            cache_dir: None,
+            cache_size: CACHE_SIZE,
+            cache_ttl: Duration::from_secs(CACHE_TTL),
+        }
+    }
+}
+
+impl HlsConfigBuilder {
+    // Délai maximal d'une requête HTTP
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    // Nombre de segments en attente dans le canal
+    pub fn bound(mut self, bound: usize) -> Self {
+        self.bound = bound;
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn proxy(mut self, url: impl Into<String>) -> Self {
+        self.proxy = Some(url.into());
+        self
+    }
+
+    // Le Client fourni remplace timeout, user_agent, header et proxy
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    pub fn variant(mut self, variant: VariantPolicy) -> Self {
+        self.variant = variant;
+        self
+    }
+
+    // La piste audio (EXT-X-MEDIA TYPE=AUDIO) du groupe de la variante choisie
+    pub fn rendition(mut self, rendition: RenditionPolicy) -> Self {
+        self.rendition = rendition;
+        self
+    }
+
+    // Codecs acceptés, en ordre de préférence
+    pub fn codecs<I, S>(mut self, codecs: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.codecs = codecs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    // Commuter entre les variantes selon le débit mesuré à chaque segment
+    pub fn adaptive(mut self, adaptive: bool) -> Self {
+        self.adaptive = adaptive;
+        self
+    }
+
+    // Nombre de segments téléchargés en parallèle pour un flux sur demande
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    pub fn gap(mut self, gap: GapPolicy) -> Self {
+        self.gap = gap;
+        self
+    }
+
+    // Les métadonnées ID3 des segments MPEG-TS sont transmises dans ce canal, dans l'ordre des segments
+    pub fn metadata(mut self, tx: Sender<Metadata>) -> Self {
+        self.metadata = Some(tx);
+        self
+    }
+
+    // L'instantané de la progression, mis à jour à chaque segment transmis
+    pub fn stats(mut self, stats: HlsStats) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    // Position de départ d'un flux sur demande. Les segments qui la précèdent ne sont pas obtenus
+    pub fn start_position(mut self, start_position: Duration) -> Self {
+        self.start_position = start_position;
+        self
+    }
+
+    pub fn live_start(mut self, live_start: LiveStart) -> Self {
+        self.live_start = live_start;
+        self
+    }
+
+    // Au-delà de ce retard sur le segment le plus récent, un flux «live» reprend à son point de départ,
+    // qui doit donc être plus près du direct (voir live_start). None, par défaut: le retard n'est pas surveillé
+    pub fn max_latency(mut self, max_latency: Option<Duration>) -> Self {
+        self.max_latency = max_latency;
+        self
+    }
+
+    // Low-Latency HLS: segments partiels (EXT-X-PART), indices de préchargement et rechargement bloquant
+    // de la playlist, si le serveur les offre
+    pub fn low_latency(mut self, low_latency: bool) -> Self {
+        self.low_latency = low_latency;
+        self
+    }
+
+    // Cache disque des segments décryptés d'un flux sur demande; les clés restent en mémoire. Le répertoire,
+    // réservé à l'utilisateur, peut être partagé entre ses processus
+    pub fn cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(dir.into());
+        self
+    }
+
+    // Taille maximale du cache en octets: les segments les moins récemment utilisés sont évincés
+    pub fn cache_size(mut self, size: u64) -> Self {
+        self.cache_size = size;
+        self
+    }
+
+    // Durée de vie d'un segment dans le cache
+    pub fn cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = ttl;
+        self
+    }
+
+    pub fn build(self) -> Result<HlsConfig> {
+        let client = match self.client {
+            Some(client) => client,
+            None => {
+                let mut headers = HeaderMap::new();
+                for (name, value) in &self.headers {
+                    headers.insert(
+                        HeaderName::try_from(name).context(format!("Échec: validation de l'entête {name}"))?,
+                        HeaderValue::try_from(value).context(format!("Échec: validation de la valeur de l'entête {name}"))?,
+                    );
+                }
+
+                let mut builder = Client::builder().timeout(self.timeout).default_headers(headers);
+                if let Some(user_agent) = self.user_agent {
+                    builder = builder.user_agent(user_agent);
+                }
+                if let Some(proxy) = self.proxy {
+                    builder = builder.proxy(Proxy::all(&proxy).context(format!("Échec: validation du proxy {proxy}"))?);
+                }
+                builder.build().context("Échec: création du Client")?
+            }
+        };
+
+        Ok(HlsConfig {
+            client,
+            max_retries: self.max_retries,
+            retry_delay: self.retry_delay,
+            bound: self.bound,
+            variant: self.variant,
+            rendition: self.rendition,
+            codecs: self.codecs,
+            adaptive: self.adaptive,
+            concurrency: self.concurrency.max(1),
+            gap: self.gap,
+            metadata: self.metadata,
+            stats: self.stats,
+            start_position: self.start_position,
+            live_start: self.live_start,
+            max_latency: self.max_latency,
+            low_latency: self.low_latency,
+            cache: match self.cache_dir {
+                Some(dir) => Some(DiskCache::new(dir, self.cache_size, self.cache_ttl)?),
+                None => None,
+            },
+        })
+    }
+}
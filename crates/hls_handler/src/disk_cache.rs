@@ -0,0 +1,305 @@
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+const EXTENSION: &str = "seg";
+// Moment de l'écriture en secondes depuis l'époque Unix, puis l'empreinte du validateur, big-endian
+const HEADER: usize = 8 + 16;
+// Les paramètres de signature connus (jetons, CloudFront, Akamai, S3), qui changent d'une session à l'autre pour
+// le même contenu. Les autres paramètres de la requête peuvent désigner le contenu
+const SIGNED: &[&str] = &[
+    "token",
+    "hdnts",
+    "hdnea",
+    "expires",
+    "signature",
+    "key-pair-id",
+    "policy",
+    "x-amz-algorithm",
+    "x-amz-credential",
+    "x-amz-date",
+    "x-amz-expires",
+    "x-amz-security-token",
+    "x-amz-signature",
+    "x-amz-signedheaders",
+];
+
+static TEMP: AtomicUsize = AtomicUsize::new(0);
+
+// Cache disque des segments décryptés. Une entrée est adressée par l'url du segment sans ses paramètres de
+// signature, et porte l'empreinte d'un validateur vérifiée à la lecture: voir address() et get(). Il peut être
+// partagé par plusieurs processus d'un même utilisateur: l'heure de modification d'une entrée est celle de son
+// dernier usage, pour l'éviction LRU. Une erreur du cache n'est jamais fatale, le segment est alors obtenu
+#[derive(Clone, Debug)]
+pub(crate) struct DiskCache {
+    dir: PathBuf,
+    max_size: u64,
+    ttl: Duration,
+    size: Arc<AtomicU64>, // Taille estimée: relevée à la création et à chaque éviction, puis cumulée
+}
+
+// FNV-1a de 128 bits, stable d'une version de Rust à l'autre
+fn fnv(data: &[u8]) -> u128 {
+    data.iter().fold(0x6c62272e07bb014262b821756295c58d_u128, |hash, &b| {
+        (hash ^ b as u128).wrapping_mul(0x0000000001000000000000000000013b)
+    })
+}
+
+// L'url sans ses paramètres de signature ni son fragment: les jetons d'une autre session désignent le même contenu
+pub(crate) fn identity(url: &str) -> String {
+    let Ok(mut url) = Url::parse(url) else {
+        return url.to_owned();
+    };
+    let query = url
+        .query_pairs()
+        .filter(|(name, _)| !SIGNED.contains(&name.to_ascii_lowercase().as_str()))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+    url.set_fragment(None);
+    match query.is_empty() {
+        true => url.set_query(None),
+        false => {
+            url.query_pairs_mut().clear().extend_pairs(query);
+        }
+    }
+    url.into()
+}
+
+// L'identité de l'url, serveur compris, et la plage
+fn address(url: &str, range: Option<&Range<usize>>) -> String {
+    let range = range.map(|range| format!("{}-{}", range.start, range.end)).unwrap_or_default();
+    let hash = fnv(&[identity(url).as_bytes(), b"\0", range.as_bytes()].concat());
+    format!("{hash:032x}")
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+// Le répertoire n'est accessible qu'à son propriétaire: il échoue s'il appartient à un autre utilisateur
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dir)
+}
+
+fn create_private_file(path: &Path) -> std::io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+impl DiskCache {
+    pub(crate) fn new(dir: PathBuf, max_size: u64, ttl: Duration) -> Result<Self> {
+        create_private_dir(&dir).context(format!("Échec: création du cache {}", dir.display()))?;
+        let cache = Self {
+            dir,
+            max_size,
+            ttl,
+            size: Arc::default(),
+        };
+        cache.evict();
+        Ok(cache)
+    }
+
+    fn path(&self, url: &str, range: Option<&Range<usize>>) -> PathBuf {
+        self.dir.join(address(url, range)).with_extension(EXTENSION)
+    }
+
+    // Une entrée expirée est retirée. Une entrée écrite sous un autre validateur, le même url désignant un autre
+    // contenu, est ignorée: elle sera remplacée
+    pub(crate) fn get(&self, url: &str, range: Option<&Range<usize>>, validator: &str) -> Option<Bytes> {
+        let path = self.path(url, range);
+        let data = fs::read(&path).ok()?;
+        let written = u64::from_be_bytes(data.get(..8)?.try_into().ok()?);
+        if u128::from_be_bytes(data.get(8..HEADER)?.try_into().ok()?) != fnv(validator.as_bytes()) {
+            return None;
+        }
+        if now().saturating_sub(written) > self.ttl.as_secs() {
+            if fs::remove_file(&path).is_ok() {
+                let len = data.len() as u64;
+                let _ = self
+                    .size
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |size| Some(size.saturating_sub(len)));
+            }
+            return None;
+        }
+        if let Ok(file) = File::options().write(true).open(&path) {
+            file.set_modified(SystemTime::now()).unwrap_or_default();
+        }
+        Some(Bytes::from(data).slice(HEADER..))
+    }
+
+    // Écrire dans un fichier temporaire unique puis le renommer, pour qu'une entrée ne soit jamais lue à
+    // moitié écrite, même si plusieurs tâches écrivent la même
+    pub(crate) fn put(&self, url: &str, range: Option<&Range<usize>>, validator: &str, data: &[u8]) {
+        let path = self.path(url, range);
+        let temp = path.with_extension(format!("{}.{}.tmp", std::process::id(), TEMP.fetch_add(1, Ordering::Relaxed)));
+        let written = create_private_file(&temp).and_then(|mut file| {
+            file.write_all(&now().to_be_bytes())?;
+            file.write_all(&fnv(validator.as_bytes()).to_be_bytes())?;
+            file.write_all(data)
+        });
+        match written.and_then(|_| fs::rename(&temp, &path)) {
+            Ok(_) => {
+                // Une entrée remplacée est comptée deux fois jusqu'à la prochaine éviction
+                let len = (HEADER + data.len()) as u64;
+                if self.size.fetch_add(len, Ordering::Relaxed) + len > self.max_size {
+                    self.evict();
+                }
+            }
+            Err(e) => {
+                eprintln!("Échec: écriture dans le cache: {e}");
+                fs::remove_file(&temp).unwrap_or_default();
+            }
+        }
+    }
+
+    // Retirer les entrées inutilisées depuis le TTL puis, si la taille maximale est dépassée, les moins
+    // récemment utilisées jusqu'à 90% de celle-ci. Seule l'éviction parcourt le répertoire
+    fn evict(&self) {
+        let Ok(dir) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        let mut entries = dir
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().extension().is_some_and(|extension| extension == EXTENSION))
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                Some((metadata.modified().ok()?, metadata.len(), entry.path()))
+            })
+            .collect::<Vec<_>>();
+        entries.sort();
+
+        let mut size = entries.iter().map(|(_, len, _)| len).sum::<u64>();
+        let low_water = match size > self.max_size {
+            true => self.max_size - self.max_size / 10,
+            false => self.max_size,
+        };
+        let expired = SystemTime::now().checked_sub(self.ttl).unwrap_or(UNIX_EPOCH);
+        for (modified, len, path) in entries {
+            if size <= low_water && modified >= expired {
+                break;
+            }
+            if fs::remove_file(path).is_ok() {
+                size -= len;
+            }
+        }
+        self.size.store(size, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lru() {
+        let dir = std::env::temp_dir().join(format!("hls_cache_{}", std::process::id()));
+        // Deux entrées de 124 octets tiennent sous 90% de la taille maximale
+        let cache = DiskCache::new(dir.clone(), 280, Duration::from_secs(3600)).unwrap();
+        let segment = [7; 100];
+        cache.put("https://cdn/seg1.aac", None, "v", &segment);
+        assert_eq!(cache.get("https://cdn/seg1.aac", None, "v").unwrap(), &segment[..]);
+        assert!(cache.get("https://cdn/seg1.aac", Some(&(0..100)), "v").is_none());
+
+        // seg1 est plus récemment utilisé que seg2: seg2 est évincé par seg3
+        cache.put("https://cdn/seg2.aac", None, "v", &segment);
+        let old = SystemTime::now() - Duration::from_secs(60);
+        File::options()
+            .write(true)
+            .open(cache.path("https://cdn/seg2.aac", None))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        cache.put("https://cdn/seg3.aac", None, "v", &segment);
+        assert!(cache.get("https://cdn/seg1.aac", None, "v").is_some());
+        assert!(cache.get("https://cdn/seg2.aac", None, "v").is_none());
+        assert!(cache.get("https://cdn/seg3.aac", None, "v").is_some());
+
+        // Expiration
+        let expired = DiskCache::new(dir.clone(), u64::MAX, Duration::ZERO).unwrap();
+        let path = expired.path("https://cdn/seg4.aac", None);
+        let header = [(now() - 10).to_be_bytes().as_slice(), &fnv(b"v").to_be_bytes()].concat();
+        fs::write(&path, [header.as_slice(), &segment].concat()).unwrap();
+        assert!(expired.get("https://cdn/seg4.aac", None, "v").is_none());
+        assert!(!path.exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn addresses() {
+        // Un autre jeton: le même segment
+        let segment = address("https://a.cdn/hls/96k/seg1.ts?token=1&asset=ep1", None);
+        assert_eq!(segment, address("https://a.cdn/hls/96k/seg1.ts?asset=ep1&token=2", None));
+        assert_eq!(
+            address("https://a.cdn/seg1.ts?Expires=1&Signature=a&Key-Pair-Id=k", None),
+            address("https://a.cdn/seg1.ts?Expires=2&Signature=b&Key-Pair-Id=k", None)
+        );
+        // Un autre serveur, un autre paramètre, une autre variante ou une autre plage: un autre segment
+        assert_ne!(segment, address("https://b.cdn/hls/96k/seg1.ts?token=1&asset=ep1", None));
+        assert_ne!(segment, address("https://a.cdn/hls/96k/seg1.ts?token=1&asset=ep2", None));
+        assert_ne!(segment, address("https://a.cdn/hls/192k/seg1.ts?token=1&asset=ep1", None));
+        assert_ne!(segment, address("https://a.cdn/hls/96k/seg1.ts?token=1&asset=ep1", Some(&(0..100))));
+    }
+
+    #[test]
+    fn collision() {
+        let dir = std::env::temp_dir().join(format!("hls_cache_collision_{}", std::process::id()));
+        let cache = DiskCache::new(dir.clone(), u64::MAX, Duration::from_secs(3600)).unwrap();
+
+        // Deux serveurs servent des contenus différents au même chemin
+        cache.put("https://a.cdn/hls/seg1.ts", None, "https://a.cdn/hls/media.m3u8#1", b"a");
+        cache.put("https://b.cdn/hls/seg1.ts", None, "https://b.cdn/hls/media.m3u8#1", b"b");
+        assert_eq!(
+            cache.get("https://a.cdn/hls/seg1.ts", None, "https://a.cdn/hls/media.m3u8#1").unwrap(),
+            &b"a"[..]
+        );
+        assert_eq!(
+            cache.get("https://b.cdn/hls/seg1.ts", None, "https://b.cdn/hls/media.m3u8#1").unwrap(),
+            &b"b"[..]
+        );
+
+        // Le même url, réutilisé par une autre playlist: l'entrée ne vaut plus
+        assert!(cache.get("https://a.cdn/hls/seg1.ts", None, "https://a.cdn/hls/autre.m3u8#1").is_none());
+        cache.put("https://a.cdn/hls/seg1.ts", None, "https://a.cdn/hls/autre.m3u8#1", b"c");
+        assert_eq!(
+            cache.get("https://a.cdn/hls/seg1.ts", None, "https://a.cdn/hls/autre.m3u8#1").unwrap(),
+            &b"c"[..]
+        );
+        assert!(cache.get("https://a.cdn/hls/seg1.ts", None, "https://a.cdn/hls/media.m3u8#1").is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("hls_cache_private_{}", std::process::id()));
+        let cache = DiskCache::new(dir.clone(), u64::MAX, Duration::from_secs(3600)).unwrap();
+        cache.put("https://cdn/seg1.aac", None, "v", b"aac");
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&dir), 0o700);
+        assert_eq!(mode(&cache.path("https://cdn/seg1.aac", None)), 0o600);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
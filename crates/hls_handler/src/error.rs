@@ -0,0 +1,77 @@
+use reqwest::StatusCode;
+use std::error::Error;
+use std::fmt;
+
+// Les erreurs du pipeline. Elles sont transmises dans un anyhow::Error avec leur contexte:
+// e.downcast_ref::<HlsError>() retrouve la plus externe
+#[derive(Debug)]
+pub enum HlsError {
+    Network(Box<dyn Error + Send + Sync>),
+    HttpStatus(StatusCode),
+    Playlist(String),
+    NoSuitableVariant(String),
+    Key(String),
+    Decrypt(String),
+    Demux(String),
+    Cancelled,
+}
+
+impl HlsError {
+    // Une nouvelle tentative pourrait réussir
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            HlsError::Network(_) => true,
+            HlsError::HttpStatus(status) => {
+                *status == StatusCode::REQUEST_TIMEOUT || *status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for HlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HlsError::Network(_) => write!(f, "Erreur réseau"),
+            HlsError::HttpStatus(status) => write!(f, "Statut HTTP {status}"),
+            HlsError::Playlist(message)
+            | HlsError::NoSuitableVariant(message)
+            | HlsError::Key(message)
+            | HlsError::Decrypt(message)
+            | HlsError::Demux(message) => write!(f, "{message}"),
+            HlsError::Cancelled => write!(f, "Téléchargement annulé"),
+        }
+    }
+}
+
+impl Error for HlsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            HlsError::Network(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn retryable() {
+        assert!(HlsError::Network("Contenu tronqué".into()).is_retryable());
+        assert!(HlsError::HttpStatus(StatusCode::SERVICE_UNAVAILABLE).is_retryable());
+        assert!(HlsError::HttpStatus(StatusCode::TOO_MANY_REQUESTS).is_retryable());
+        assert!(!HlsError::HttpStatus(StatusCode::FORBIDDEN).is_retryable());
+        assert!(!HlsError::Demux("Pas de PID 0".into()).is_retryable());
+
+        // Le contexte le plus externe a préséance
+        let e = Err::<(), _>(HlsError::HttpStatus(StatusCode::NOT_FOUND))
+            .context("Échec: get")
+            .context(HlsError::Key("Échec: obtention de la clé".into()))
+            .unwrap_err();
+        assert!(matches!(e.downcast_ref::<HlsError>(), Some(HlsError::Key(_))));
+        assert_eq!(format!("{e:#}"), "Échec: obtention de la clé: Échec: get: Statut HTTP 404 Not Found");
+    }
+}
@@ -0,0 +1,207 @@
+use std::time::Duration;
+
+// Une trame texte ID3v2: TIT2, TPE1, ... ou TXXX avec sa description
+#[derive(Clone, Debug, PartialEq)]
+pub struct Id3Frame {
+    pub id: String,
+    pub description: Option<String>,
+    pub value: String,
+}
+
+// Les métadonnées minutées d'un segment et leur PTS
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metadata {
+    pub sequence: usize,
+    pub pts: Option<Duration>,
+    pub frames: Vec<Id3Frame>,
+}
+
+impl Metadata {
+    pub fn frame(&self, id: &str) -> Option<&str> {
+        self.frames.iter().find(|frame| frame.id == id).map(|frame| frame.value.as_str())
+    }
+
+    // «Interprète - Titre», ou le titre seul
+    pub fn title(&self) -> Option<String> {
+        match (self.frame("TPE1"), self.frame("TIT2")) {
+            (Some(artist), Some(title)) => Some(format!("{artist} - {title}")),
+            (None, Some(title)) => Some(title.to_owned()),
+            _ => None,
+        }
+    }
+}
+
+fn syncsafe(b: &[u8]) -> usize {
+    b.iter().fold(0, |size, &b| size << 7 | (b & 0x7F) as usize)
+}
+
+fn latin1(data: &[u8]) -> String {
+    data.iter().map(|&b| b as char).collect()
+}
+
+fn utf16(data: &[u8], encoding: u8) -> String {
+    // Le BOM est facultatif avec l'encodage 2, toujours gros-boutiste
+    let (data, little_endian) = match data {
+        [0xFF, 0xFE, data @ ..] if encoding == 1 => (data, true),
+        [0xFE, 0xFF, data @ ..] => (data, false),
+        _ => (data, false),
+    };
+    let units = data.chunks_exact(2).map(|b| match little_endian {
+        true => u16::from_le_bytes([b[0], b[1]]),
+        false => u16::from_be_bytes([b[0], b[1]]),
+    });
+    char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect()
+}
+
+// Les chaînes d'une trame texte, séparées par des terminateurs selon leur encodage
+fn strings(encoding: u8, data: &[u8]) -> Vec<String> {
+    match encoding {
+        1 | 2 => {
+            let mut strings = Vec::new();
+            let mut start = 0;
+            let mut i = 0;
+            while i + 1 < data.len() {
+                if data[i] == 0 && data[i + 1] == 0 {
+                    strings.push(utf16(&data[start..i], encoding));
+                    start = i + 2;
+                }
+                i += 2;
+            }
+            strings.push(utf16(&data[start..data.len() & !1], encoding));
+            strings
+        }
+        3 => data.split(|&b| b == 0).map(|s| String::from_utf8_lossy(s).into_owned()).collect(),
+        _ => data.split(|&b| b == 0).map(latin1).collect(),
+    }
+}
+
+fn text_frame(id: &str, body: &[u8]) -> Option<Id3Frame> {
+    let (&encoding, data) = body.split_first()?;
+    let mut strings = strings(encoding, data);
+    while strings.last().is_some_and(String::is_empty) {
+        strings.pop();
+    }
+    let description = match id {
+        "TXXX" if !strings.is_empty() => Some(strings.remove(0)),
+        _ => None,
+    };
+    Some(Id3Frame {
+        id: id.to_owned(),
+        description,
+        value: strings.join("/"), // Les valeurs multiples de ID3v2.4
+    })
+}
+
+// Les trames texte des étiquettes ID3v2.3 et ID3v2.4 d'un PES de métadonnées. Les autres trames,
+// dont l'horodatage PRIV d'Apple, sont ignorées
+pub(crate) fn parse(mut data: &[u8]) -> Vec<Id3Frame> {
+    let mut frames = Vec::new();
+
+    while let [b'I', b'D', b'3', version @ (3 | 4), _, flags, s0, s1, s2, s3, ..] = *data {
+        let size = syncsafe(&[s0, s1, s2, s3]);
+        let Some(tag) = data.get(10..10 + size) else {
+            break; // Étiquette tronquée
+        };
+        data = &data[10 + size..];
+
+        let mut tag = tag;
+        if flags & 0x40 != 0 {
+            // Entête étendu: sa taille l'inclut en v2.4 seulement
+            let Some(&[e0, e1, e2, e3]) = tag.get(..4) else { continue };
+            let skip = match version {
+                4 => syncsafe(&[e0, e1, e2, e3]),
+                _ => 4 + u32::from_be_bytes([e0, e1, e2, e3]) as usize,
+            };
+            tag = tag.get(skip..).unwrap_or_default();
+        }
+
+        while let [i0, i1, i2, i3, z0, z1, z2, z3, _, format, ..] = *tag {
+            if i0 == 0 {
+                break; // Remplissage
+            }
+            let size = match version {
+                4 => syncsafe(&[z0, z1, z2, z3]),
+                _ => u32::from_be_bytes([z0, z1, z2, z3]) as usize,
+            };
+            let Some(body) = tag.get(10..10 + size) else { break };
+            tag = &tag[10 + size..];
+
+            // Les trames compressées ou chiffrées ne sont pas prises en charge
+            let unsupported = match version {
+                4 => format & 0x0C != 0,
+                _ => format & 0xC0 != 0,
+            };
+            let id = latin1(&[i0, i1, i2, i3]);
+            if id.starts_with('T') && !unsupported {
+                frames.extend(text_frame(&id, body));
+            }
+        }
+    }
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Une taille inférieure à 128 s'écrit de la même façon en v2.3 et en v2.4
+    fn frame(id: &str, body: &[u8]) -> Vec<u8> {
+        let mut frame = id.as_bytes().to_vec();
+        frame.extend_from_slice(&[0, 0, 0, body.len() as u8]);
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(body);
+        frame
+    }
+
+    fn tag(version: u8, frames: &[u8]) -> Vec<u8> {
+        let len = frames.len() + 4; // Avec du remplissage
+        let mut tag = vec![b'I', b'D', b'3', version, 0, 0, 0, 0, (len >> 7) as u8, (len & 0x7F) as u8];
+        tag.extend_from_slice(frames);
+        tag.extend_from_slice(&[0; 4]);
+        tag
+    }
+
+    #[test]
+    fn frames() {
+        // ID3v2.4 en UTF-8, puis ID3v2.3 en UTF-16 et ISO-8859-1 dans le même PES
+        let mut v4 = frame("TIT2", b"\x03Hymne \xc3\xa0 l'amour");
+        v4.extend(frame("PRIV", b"\x00\x00\x00\x00"));
+        let mut v3 = frame("TPE1", b"\x01\xff\xfeE\x00d\x00i\x00t\x00h\x00\x00\x00");
+        v3.extend(frame("TXXX", b"\x00\xe9mission\x00Bis"));
+        let mut data = tag(4, &v4);
+        data.extend(tag(3, &v3));
+
+        let frames = parse(&data);
+        assert_eq!(
+            frames,
+            [
+                Id3Frame {
+                    id: "TIT2".to_owned(),
+                    description: None,
+                    value: "Hymne à l'amour".to_owned()
+                },
+                Id3Frame {
+                    id: "TPE1".to_owned(),
+                    description: None,
+                    value: "Edith".to_owned()
+                },
+                Id3Frame {
+                    id: "TXXX".to_owned(),
+                    description: Some("émission".to_owned()),
+                    value: "Bis".to_owned()
+                },
+            ]
+        );
+
+        let metadata = Metadata {
+            sequence: 1,
+            pts: None,
+            frames,
+        };
+        assert_eq!(metadata.title().as_deref(), Some("Edith - Hymne à l'amour"));
+
+        // Étiquette tronquée
+        assert!(parse(&data[..20]).is_empty());
+        assert!(parse(b"garbage").is_empty());
+    }
+}
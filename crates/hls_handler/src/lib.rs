@@ -1,484 +1,1411 @@
+mod abr;
+mod adts;
+mod cbc;
+mod config;
+mod disk_cache;
+mod error;
+mod id3;
+mod rendition;
+mod stats;
+mod tags;
+mod ts_demux;
+mod variant;
+
+use abr::Ladder;
+pub use adts::{AdtsConfig, AdtsFrame, AdtsFrames, AdtsSummary};
 use anyhow::{Context, Error, Result, anyhow, bail};
-use hls_m3u8::tags::VariantStream;
-use hls_m3u8::types::EncryptionMethod;
-use hls_m3u8::{Decryptable, MasterPlaylist, MediaPlaylist};
-use mpeg2ts::ts::{Pid, ReadTsPacket, TsPacketReader, TsPayload};
-use reqwest::Client;
-use std::collections::HashMap;
+use bytes::Bytes;
+use cbc::{CbcDecryptor, SegmentKey};
+pub use config::{GapPolicy, HlsConfig, HlsConfigBuilder, LiveStart};
+pub use error::HlsError;
+use hls_m3u8::tags::{ExtXKey, ExtXMap, VariantStream};
+use hls_m3u8::types::{ByteRange, DecryptionKey, EncryptionMethod, KeyFormat, PlaylistType};
+use hls_m3u8::{Decryptable, MasterPlaylist, MediaPlaylist, MediaSegment};
+pub use id3::{Id3Frame, Metadata};
+pub use rendition::{Rendition, RenditionPolicy};
+use reqwest::header::{ETAG, IF_NONE_MATCH, RANGE, RETRY_AFTER};
+use reqwest::{Response, StatusCode};
+pub use stats::{HlsStats, Stats};
+use std::collections::VecDeque;
+use std::collections::hash_map::RandomState;
 use std::convert::TryFrom;
-use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+use std::hash::{BuildHasher, Hasher};
+use std::ops::Range;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::mpsc::{Receiver, sync_channel};
+use std::task::{Context as TaskContext, Poll};
 use std::thread;
 use std::time::{Duration, Instant};
+use tags::{Part, Tags};
+use tokio::sync::mpsc::{self, Sender};
+use tokio::task::{AbortHandle, JoinHandle};
+use tokio_stream::{Stream, StreamExt};
 use url::{ParseError, Url};
+pub use variant::VariantPolicy;
 
-enum InitState {
-    Pid0,
-    Pmt(Pid),
-}
-
-type Message = Result<Vec<u8>>;
+type Message = Result<Segment>;
 
-const TIME_OUT: u64 = 30;
-const MAX_RETRIES: usize = 20;
-const RETRY_DELAY: u64 = 250;
-const BOUND: usize = 3;
+// Un segment prêt à décoder et ses métadonnées
+#[derive(Clone, Debug)]
+pub struct Segment {
+    pub data: Bytes,
+    pub sequence: usize,
+    pub duration: Duration,
+    pub discontinuity: bool, // Le décodeur doit être réinitialisé avant ces données
+    pub program_date_time: Option<String>,
+    pub adts: Option<AdtsSummary>, // Les trames ADTS validées, sauf pour fMP4
+    pub trim: Duration,            // Audio à retrancher au début du premier segment pour atteindre la position de départ
+    pub part: Option<usize>,       // Index de la partie (EXT-X-PART) dans son segment, en LL-HLS
+}
 
-fn decrypt_aes128(key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>> {
-    let cipher = libaes::Cipher::new_128(key.try_into().context("La clé n'a pas une longueur de 16 bytes")?);
-    let decrypted = cipher.cbc_decrypt(iv, data);
-    match decrypted.is_empty() {
-        false => Ok(decrypted),
-        true => Err(anyhow!("La décryption a échoué")),
+impl Segment {
+    fn new(media_segment: &MediaSegment, data: Bytes) -> Self {
+        Self {
+            data,
+            sequence: media_segment.number(),
+            duration: media_segment.duration.duration(),
+            discontinuity: media_segment.has_discontinuity,
+            program_date_time: media_segment.program_date_time.as_ref().map(|pdt| pdt.date_time.to_string()),
+            adts: None,
+            trim: Duration::ZERO,
+            part: None,
+        }
     }
 }
 
+const MAX_KEYS: usize = 8;
+const PTS_HZ: f64 = 90_000.0;
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+const MAX_CAPACITY: u64 = 16 << 20; // Allocation initiale maximale selon Content-Length
+
 fn base_or_join(base: &Url, url: &str) -> Result<Url> {
     match Url::parse(url) {
         Ok(url) => Ok(url),
-        Err(ParseError::RelativeUrlWithoutBase) => base.join(url).context(format!("Échec: join de l'url {}", url)),
-        Err(e) => Err(e.into()),
+        Err(ParseError::RelativeUrlWithoutBase) => base
+            .join(url)
+            .map_err(|e| HlsError::Playlist(format!("Échec: join de l'url {url}: {e}")).into()),
+        Err(e) => Err(HlsError::Playlist(format!("Url {url} invalide: {e}")).into()),
+    }
+}
+
+// Résultat d'une tentative: les erreurs transitoires (transport, 408, 429, 5xx, contenu tronqué) méritent une reprise
+enum Attempt {
+    Done(Bytes, Option<String>), // Le contenu, décrypté s'il y a lieu, et son ETag
+    NotModified,
+    Retry(HlsError, Option<Duration>),
+    Fail(HlsError),
+}
+
+async fn attempt(url: &str, range: Option<&Range<usize>>, etag: Option<&str>, key: Option<&SegmentKey>, config: &HlsConfig) -> Attempt {
+    let mut request = config.client.get(url);
+    if let Some(range) = range {
+        request = request.header(RANGE, format!("bytes={}-{}", range.start, range.end.saturating_sub(1)));
+    }
+    if let Some(etag) = etag {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    let mut response = match request.send().await {
+        Ok(response) => response,
+        Err(e) => return Attempt::Retry(HlsError::Network(e.into()), None),
+    };
+
+    let status = response.status();
+    if status == StatusCode::NOT_MODIFIED {
+        return Attempt::NotModified;
+    }
+    if !status.is_success() {
+        let error = HlsError::HttpStatus(status);
+        return match status {
+            StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS => Attempt::Retry(error, retry_after(&response)),
+            status if status.is_server_error() => Attempt::Retry(error, retry_after(&response)),
+            _ => Attempt::Fail(error),
+        };
+    }
+
+    let expected = response.content_length();
+    let etag = response.headers().get(ETAG).and_then(|etag| etag.to_str().ok()).map(str::to_owned);
+    // Le contenu chiffré est décrypté au fil des morceaux, à moins qu'il faille d'abord en extraire la plage:
+    // la réponse chiffrée n'est pas conservée à côté du contenu décrypté. Le segment n'est toutefois
+    // transmis qu'une fois complet et son remplissage vérifié
+    let whole = range.is_some() && status != StatusCode::PARTIAL_CONTENT;
+    let mut decryptor = key.filter(|_| !whole).map(CbcDecryptor::new);
+    let mut body = Vec::with_capacity(expected.unwrap_or_default().min(MAX_CAPACITY) as usize);
+    let mut received = 0;
+    loop {
+        match response.chunk().await {
+            Ok(Some(chunk)) => {
+                received += chunk.len();
+                match &mut decryptor {
+                    Some(decryptor) => decryptor.update(&chunk, &mut body),
+                    None => body.extend_from_slice(&chunk),
+                }
+            }
+            Ok(None) => break,
+            Err(e) => return Attempt::Retry(HlsError::Network(e.into()), None),
+        }
+    }
+    if let Some(len) = expected
+        && len != received as u64
+    {
+        return Attempt::Retry(HlsError::Network(format!("Contenu tronqué: {received} octets sur {len}").into()), None);
+    }
+
+    match range {
+        Some(range) if !whole && received != range.len() => {
+            return Attempt::Retry(
+                HlsError::Network(format!("Plage tronquée: {received} octets sur {}", range.len()).into()),
+                None,
+            );
+        }
+        // Le serveur ignore l'entête Range et retourne tout le contenu
+        Some(range) if whole => {
+            if received < range.end {
+                return Attempt::Fail(HlsError::Playlist(format!("La plage {range:?} dépasse le contenu de {received} octets")));
+            }
+            body.truncate(range.end);
+            body.drain(..range.start);
+            if let Some(key) = key {
+                let mut slice = CbcDecryptor::new(key);
+                let mut decrypted = Vec::with_capacity(body.len());
+                slice.update(&body, &mut decrypted);
+                (body, decryptor) = (decrypted, Some(slice));
+            }
+        }
+        _ => (),
     }
+
+    if let Some(decryptor) = decryptor
+        && let Err(e) = decryptor.finish(&mut body)
+    {
+        return Attempt::Fail(e);
+    }
+    Attempt::Done(body.into(), etag)
+}
+
+// Retry-After en secondes seulement
+fn retry_after(response: &Response) -> Option<Duration> {
+    let seconds = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim().parse::<u64>().ok()?;
+    Some(Duration::from_secs(seconds).min(MAX_RETRY_AFTER))
+}
+
+// Délai exponentiel à partir de retry_delay, plus une gigue d'au plus 50%
+fn backoff(retry_delay: Duration, retries: usize) -> Duration {
+    let delay = retry_delay.saturating_mul(1 << retries.min(16)).min(MAX_BACKOFF);
+    let jitter = RandomState::new().build_hasher().finish() % (delay.as_millis() as u64 / 2 + 1);
+    delay + Duration::from_millis(jitter)
+}
+
+async fn get(url: &str, config: &HlsConfig) -> Result<Bytes> {
+    get_range(url, None, config).await
+}
+
+// Une partie du contenu seulement (EXT-X-BYTERANGE)
+async fn get_range(url: &str, range: Option<Range<usize>>, config: &HlsConfig) -> Result<Bytes> {
+    get_segment(url, range, None, config).await
+}
+
+// Un segment, ou une section d'initialisation, décrypté à la réception
+async fn get_segment(url: &str, range: Option<Range<usize>>, key: Option<&SegmentKey>, config: &HlsConfig) -> Result<Bytes> {
+    // Sans If-None-Match, le contenu est toujours retourné
+    Ok(fetch(url, range, None, key, config).await?.map(|(body, _)| body).unwrap_or_default())
+}
+
+// Ce qui désigne le contenu d'un segment, au-delà de son url: la playlist qui le liste et son numéro de séquence.
+// Une section d'initialisation n'a pas de numéro
+fn validator(media_url: &Url, number: Option<usize>) -> String {
+    let media_url = disk_cache::identity(media_url.as_str());
+    number.map_or_else(|| media_url.clone(), |number| format!("{media_url}#{number}"))
 }
 
-async fn get(url: &str, client: &Client) -> Result<Vec<u8>> {
+// Un segment décrypté du cache disque. Sa clé n'a pas à être obtenue
+async fn from_cache(url: &str, range: Option<&Range<usize>>, validator: &str, config: &HlsConfig) -> Option<Bytes> {
+    let cache = config.cache.clone()?;
+    let (url, range, validator) = (url.to_owned(), range.cloned(), validator.to_owned());
+    tokio::task::spawn_blocking(move || cache.get(&url, range.as_ref(), &validator))
+        .await
+        .ok()
+        .flatten()
+}
+
+async fn to_cache(url: &str, range: Option<&Range<usize>>, validator: &str, data: &Bytes, config: &HlsConfig) {
+    if let Some(cache) = config.cache.clone() {
+        let (url, range, validator, data) = (url.to_owned(), range.cloned(), validator.to_owned(), data.clone());
+        tokio::task::spawn_blocking(move || cache.put(&url, range.as_ref(), &validator, &data))
+            .await
+            .unwrap_or_default();
+    }
+}
+
+// Le contenu et son ETag, ou None s'il n'a pas changé depuis l'ETag donné
+async fn fetch(
+    url: &str,
+    range: Option<Range<usize>>,
+    etag: Option<&str>,
+    key: Option<&SegmentKey>,
+    config: &HlsConfig,
+) -> Result<Option<(Bytes, Option<String>)>> {
     let mut retries = 0;
     loop {
-        match client.get(url).send().await {
-            Ok(response) => break Ok(response.bytes().await?.to_vec()),
-            Err(e) => {
-                retries += 1;
-                if retries > MAX_RETRIES {
-                    bail!("get {url} a échoué après {MAX_RETRIES} tentatives");
-                } else {
-                    eprintln!("{:#}", Error::new(e).context(format!("Échec: get {url}")));
-                    tokio::time::sleep(Duration::from_millis(RETRY_DELAY)).await;
-                    continue;
+        match attempt(url, range.as_ref(), etag, key, config).await {
+            Attempt::Done(body, etag) => {
+                config.stats.update(|stats| stats.bytes += body.len() as u64);
+                break Ok(Some((body, etag)));
+            }
+            Attempt::NotModified => break Ok(None),
+            Attempt::Fail(e) => break Err(Error::new(e).context(format!("Échec: get {url}"))),
+            Attempt::Retry(e, retry_after) => {
+                if retries == config.max_retries {
+                    break Err(Error::new(e).context(format!("get {url} a échoué après {} tentatives", retries + 1)));
                 }
+                eprintln!("{:#}", Error::new(e).context(format!("Échec: get {url}")));
+                tokio::time::sleep(retry_after.unwrap_or_else(|| backoff(config.retry_delay, retries))).await;
+                retries += 1;
+                config.stats.update(|stats| stats.retries += 1);
             }
         }
     }
 }
 
-// Le segment est un fichier MPEG-TS encrypté qui contient du AAC
-async fn hls_on_demand1(media_url: Url, client: Client, tx: SyncSender<Message>) {
-    let response = match get(media_url.as_str(), &client).await {
-        Ok(response) => String::from_utf8(response).unwrap_or_default(),
-        Err(e) => {
-            tx.send(Err(e)).unwrap_or_default();
-            return;
+// Le segment est un fichier MPEG-TS qui contient du AAC ou un fichier AAC
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Format {
+    Ts,
+    Aac,
+    Fmp4,
+}
+
+impl Format {
+    // Le format est déduit de l'extension de l'URI du segment
+    fn from_uri(uri: &str) -> Option<Self> {
+        let path = uri.split(['?', '#']).next().unwrap_or_default();
+        match path.rsplit_once('.').map(|(_, ext)| ext.to_ascii_lowercase()) {
+            Some(ext) if ext == "ts" => Some(Format::Ts),
+            Some(ext) if ext == "aac" || ext == "adts" => Some(Format::Aac),
+            Some(ext) if ["mp4", "m4s", "m4a", "cmfa"].contains(&ext.as_str()) => Some(Format::Fmp4),
+            _ => None,
         }
-    };
+    }
 
-    let media = match MediaPlaylist::try_from(response.as_str()).context("Échec: validation de MediaPlayList") {
-        Ok(media) => media,
-        Err(e) => {
-            tx.send(Err(e)).unwrap_or_default();
-            return;
+    // Sinon, un paquet TS commence toujours par l'octet de synchronisation 0x47 et un fichier MP4 par une boîte
+    fn sniff(data: &[u8]) -> Self {
+        match data.first() {
+            Some(0x47) if data.len() <= 188 || data[188] == 0x47 => Format::Ts,
+            _ if data
+                .get(4..8)
+                .is_some_and(|box_type| [b"ftyp", b"styp", b"moof", b"sidx"].iter().any(|t| t[..] == *box_type)) =>
+            {
+                Format::Fmp4
+            }
+            _ => Format::Aac,
         }
-    };
+    }
+}
 
-    let mut cache: HashMap<String, Vec<u8>> = HashMap::new();
+async fn get_media_playlist(media_url: &Url, config: &HlsConfig) -> Result<(MediaPlaylist<'static>, Tags)> {
+    parse_media_playlist(std::str::from_utf8(&get(media_url.as_str(), config).await?).unwrap_or_default())
+}
 
-    for (_, media_segment) in media.segments {
-        let segment_url = match base_or_join(&media_url, media_segment.uri()).context("Échec: base_or_join de l'url media segment") {
-            Ok(url) => url,
-            Err(e) => {
-                tx.send(Err(e)).unwrap_or_default();
-                return;
-            }
-        };
-        let segment_response = match get(segment_url.as_str(), &client).await {
-            Ok(response) => response,
-            Err(e) => {
-                tx.send(Err(e)).unwrap_or_default();
-                return;
-            }
-        };
+// La playlist de la variante, ou d'une variante redondante si elle échoue
+async fn failover_media_playlist(ladder: &mut Ladder, config: &HlsConfig) -> Result<(MediaPlaylist<'static>, Tags)> {
+    loop {
+        match get_media_playlist(ladder.url(), config).await {
+            Ok(playlist) => break Ok(playlist),
+            Err(e) if ladder.failover() => eprintln!("{e:#}"),
+            Err(e) => break Err(e),
+        }
+    }
+}
 
-        let keys = media_segment.keys();
+fn parse_media_playlist(playlist: &str) -> Result<(MediaPlaylist<'static>, Tags)> {
+    let playlist = tags::expand_skip(playlist);
+    let media = MediaPlaylist::from_str(&playlist).map_err(|e| HlsError::Playlist(format!("Échec: validation de MediaPlayList: {e}")))?;
+    let tags = Tags::parse(&playlist, media.media_sequence);
+    Ok((media, tags))
+}
 
-        let decrypted = if keys.is_empty() {
-            segment_response
-        } else {
-            let key = keys.iter().find(|k| k.method == EncryptionMethod::Aes128);
+// La dernière playlist rechargée et son ETag, pour une requête conditionnelle (If-None-Match)
+#[derive(Default)]
+struct PlaylistCache {
+    url: Option<Url>,
+    etag: Option<String>,
+    playlist: String,
+}
 
-            let (uri, iv) = match key {
-                Some(key) => (key.uri().as_ref(), key.iv.to_slice()),
-                None => {
-                    tx.send(Err(anyhow!("Le segment n'est pas chiffré avec AES-128"))).unwrap_or_default();
-                    return;
-                }
-            };
+// Une réponse 304 réutilise la playlist précédente. L'ETag vaut pour l'url exact, paramètres compris
+async fn reload_media_playlist(url: &Url, cache: &mut PlaylistCache, config: &HlsConfig) -> Result<(MediaPlaylist<'static>, Tags)> {
+    let etag = cache.etag.as_deref().filter(|_| cache.url.as_ref() == Some(url));
+    if let Some((body, etag)) = fetch(url.as_str(), None, etag, None, config).await? {
+        *cache = PlaylistCache {
+            url: Some(url.clone()),
+            etag,
+            playlist: std::str::from_utf8(&body).unwrap_or_default().to_owned(),
+        };
+    }
+    parse_media_playlist(&cache.playlist)
+}
 
-            let key = match cache.get(uri) {
-                Some(key) => key,
-                None => match get(uri, &client).await {
-                    Ok(response) => {
-                        cache.insert(uri.to_owned(), response);
-                        cache.get(uri).unwrap()
-                    }
-                    Err(e) => {
-                        tx.send(Err(e)).unwrap_or_default();
-                        return;
-                    }
-                },
-            };
+// Rechargement bloquant (EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD): le serveur répond dès que la partie
+// demandée est disponible. Une mise à jour partielle (EXT-X-SKIP) allège la réponse
+fn blocking_url(url: &Url, msn: usize, part: usize, skip: bool) -> Url {
+    let mut url = url.clone();
+    url.query_pairs_mut()
+        .append_pair("_HLS_msn", &msn.to_string())
+        .append_pair("_HLS_part", &part.to_string());
+    if skip {
+        url.query_pairs_mut().append_pair("_HLS_skip", "YES");
+    }
+    url
+}
 
-            let iv = match iv {
-                Some(iv) => iv,
-                None => {
-                    tx.send(Err(anyhow!("Initialization Vector manquant"))).unwrap_or_default();
-                    return;
-                }
-            };
+// Une plage sans début commence au début du contenu
+fn byte_range(range: Option<ByteRange>) -> Option<Range<usize>> {
+    range.map(|range| range.start().unwrap_or(0)..range.end())
+}
 
-            match decrypt_aes128(key, &iv, &segment_response) {
-                Ok(decrypted) => decrypted,
-                Err(e) => {
-                    tx.send(Err(e)).unwrap_or_default();
-                    return;
+// Une playlist sans EXT-X-ENDLIST est «live», à moins d'être de type VOD
+fn is_live(media: &MediaPlaylist) -> bool {
+    !media.has_end_list && media.playlist_type != Some(PlaylistType::Vod)
+}
+
+// Cache des clés AES-128 selon leur url absolu. Les plus anciennes sont évincées lors de la rotation des clés
+#[derive(Default)]
+struct KeyCache {
+    keys: VecDeque<(Url, Bytes)>,
+}
+
+impl KeyCache {
+    async fn get(&mut self, url: Url, config: &HlsConfig) -> Result<&[u8]> {
+        let index = match self.keys.iter().position(|(key_url, _)| *key_url == url) {
+            Some(index) => index,
+            None => {
+                // Les clés ne sont jamais écrites sur disque
+                let key = get(url.as_str(), config)
+                    .await
+                    .context(HlsError::Key("Échec: obtention de la clé".to_owned()))?;
+                if self.keys.len() == MAX_KEYS {
+                    self.keys.pop_front();
                 }
+                self.keys.push_back((url, key));
+                self.keys.len() - 1
             }
         };
+        Ok(&self.keys[index].1)
+    }
+}
 
-        let mut ts = TsPacketReader::new(decrypted.as_slice());
+type InitSection = (Url, Option<Range<usize>>);
 
-        // Obtenir le pid du premier programme
-        let mut state = InitState::Pid0;
-        let program_pid = loop {
-            let packet = match ts.read_ts_packet().context("Échec: lecture d'un paquet TS") {
-                Ok(packet) => match packet {
-                    Some(packet) => packet,
-                    None => {
-                        tx.send(Err(anyhow!("Fin prématurée des paquets"))).unwrap_or_default();
-                        return;
-                    }
-                },
-                Err(e) => {
-                    tx.send(Err(e)).unwrap_or_default();
-                    return;
-                }
-            };
+// L'état partagé par les segments d'un flux: les clés et la section d'initialisation fMP4 transmise au décodeur
+#[derive(Default)]
+struct Session {
+    keys: KeyCache,
+    init: Option<(InitSection, Bytes)>,
+    adts: Option<AdtsConfig>, // Pour générer du silence à la place des segments EXT-X-GAP
+}
+
+// Sans IV, le numéro de séquence du segment sert d'IV (RFC 8216, 5.2)
+fn segment_iv(key: &DecryptionKey, number: usize) -> [u8; 16] {
+    key.iv.to_slice().unwrap_or_else(|| (number as u128).to_be_bytes())
+}
+
+// La clé d'un segment, ou d'une section d'initialisation, pour le décrypter à la réception
+async fn segment_key(
+    media_url: &Url,
+    keys: Vec<&DecryptionKey<'_>>,
+    number: usize,
+    config: &HlsConfig,
+    cache: &mut KeyCache,
+) -> Result<Option<SegmentKey>> {
+    if keys.is_empty() {
+        return Ok(None);
+    }
+
+    // Seul le format «identity» correspond à une clé AES-128 de 16 octets
+    let key = match keys
+        .iter()
+        .find(|k| k.method == EncryptionMethod::Aes128 && k.format.as_ref().is_none_or(|f| *f == KeyFormat::Identity))
+    {
+        Some(key) => key,
+        None => bail!(HlsError::Key("Le segment n'est pas chiffré avec AES-128".to_owned())),
+    };
+
+    let key_url = base_or_join(media_url, key.uri()).context("Échec: base_or_join de l'url de la clé")?;
+    let iv = segment_iv(key, number);
+    Ok(Some(SegmentKey::new(cache.get(key_url, config).await?, &iv)?))
+}
+
+// Extraire le AAC d'un segment MPEG-TS et ses métadonnées ID3
+fn demux_ts(data: &[u8], sequence: usize) -> Result<(Bytes, Vec<Metadata>)> {
+    let demuxed = ts_demux::demux(data)?;
+    let metadata = demuxed
+        .metadata
+        .into_iter()
+        .map(|pes| Metadata {
+            sequence,
+            pts: pes.pts.map(|pts| Duration::from_secs_f64(pts as f64 / PTS_HZ)),
+            frames: id3::parse(&pes.data),
+        })
+        .filter(|metadata| !metadata.frames.is_empty())
+        .collect();
+    // Un seul PES est transmis tel quel, sinon ils sont mis bout à bout
+    let audio = match <[_; 1]>::try_from(demuxed.audio) {
+        Ok([pes]) => pes.data,
+        Err(audio) => {
+            let mut stream = Vec::with_capacity(audio.iter().map(|pes| pes.data.len()).sum());
+            audio.iter().for_each(|pes| stream.extend_from_slice(&pes.data));
+            stream.into()
+        }
+    };
+    Ok((audio, metadata))
+}
+
+// La section d'initialisation (EXT-X-MAP) s'applique aux segments qui la suivent, jusqu'à la prochaine
+fn segment_map<'a>(media: &'a MediaPlaylist<'static>, number: usize) -> Option<&'a ExtXMap<'static>> {
+    media
+        .segments
+        .values()
+        .take_while(|media_segment| media_segment.number() <= number)
+        .filter_map(|media_segment| media_segment.map.as_ref())
+        .last()
+}
 
-            match state {
-                InitState::Pid0 => {
-                    match packet.header.pid.as_u16() {
-                        0 => match packet.payload {
-                            Some(payload) => match payload {
-                                TsPayload::Pat(pat) => {
-                                    state = InitState::Pmt(pat.table[0].program_map_pid);
-                                    continue;
-                                }
-                                _ => {
-                                    tx.send(Err(anyhow!("Pas de PAT dans le PID 0"))).unwrap_or_default();
-                                    return;
-                                }
-                            },
-                            None => {
-                                tx.send(Err(anyhow!("Pas de payload dans le PID 0"))).unwrap_or_default();
-                                return;
-                            }
-                        },
-                        1..=31 | 8191 => continue,
-                        _ => {
-                            tx.send(Err(anyhow!("Pas de PID 0"))).unwrap_or_default();
-                            return;
+// Extraire le AAC du segment décrypté s'il s'agit de MPEG-TS, sans copier un flux ADTS. Un segment fMP4 est précédé
+// de sa section d'initialisation lorsque celle-ci change, ce qui est une discontinuité pour le décodeur, et après
+// toute discontinuité: le nouveau décodeur en a besoin. restart signale une discontinuité que la playlist n'annonce pas
+async fn process_segment(
+    media_url: &Url,
+    media_segment: &MediaSegment<'_>,
+    map: Option<&ExtXMap<'_>>,
+    decrypted: Bytes,
+    restart: bool,
+    config: &HlsConfig,
+    session: &mut Session,
+) -> Result<Segment> {
+    let number = media_segment.number();
+    let discontinuity = media_segment.has_discontinuity || restart;
+    let format = match map {
+        Some(_) => Format::Fmp4,
+        None => Format::from_uri(media_segment.uri()).unwrap_or_else(|| Format::sniff(&decrypted)),
+    };
+    let (data, reset, summary) = match (format, map) {
+        (Format::Fmp4, Some(map)) => {
+            let init_url = base_or_join(media_url, map.uri()).context("Échec: base_or_join de l'url de la section d'initialisation")?;
+            let init_section = (init_url, byte_range(map.range()));
+            let current = session
+                .init
+                .as_ref()
+                .filter(|(section, _)| *section == init_section)
+                .map(|(_, init)| init.clone());
+            let init = match current {
+                Some(_) if !discontinuity => return Ok(Segment::new(media_segment, decrypted)),
+                Some(init) => init,
+                None => {
+                    let (init_url, init_range) = (init_section.0.as_str(), init_section.1.as_ref());
+                    let validator = validator(media_url, None);
+                    match from_cache(init_url, init_range, &validator, config).await {
+                        Some(init) => init,
+                        None => {
+                            let key = segment_key(media_url, map.keys(), number, config, &mut session.keys).await?;
+                            let init = get_segment(init_url, init_range.cloned(), key.as_ref(), config)
+                                .await
+                                .context("Échec: obtention de la section d'initialisation")?;
+                            to_cache(init_url, init_range, &validator, &init, config).await;
+                            init
                         }
-                    };
+                    }
                 }
-                InitState::Pmt(pid) => {
-                    if packet.header.pid == pid {
-                        match packet.payload {
-                            Some(payload) => match payload {
-                                TsPayload::Pmt(pmt) => break pmt.es_info[0].elementary_pid,
-                                _ => {
-                                    tx.send(Err(anyhow!("Pas de PMT dans le PID {}", pid.as_u16()))).unwrap_or_default();
-                                    return;
-                                }
-                            },
-                            None => {
-                                tx.send(Err(anyhow!("Pas de payload dans le PID {}", pid.as_u16()))).unwrap_or_default();
-                                return;
-                            }
-                        }
-                    } else {
-                        tx.send(Err(anyhow!("Pas de PID {}", pid.as_u16()))).unwrap_or_default();
-                        return;
-                    };
+            };
+            let reset = session.init.replace((init_section, init.clone())).is_some();
+            let mut data = Vec::with_capacity(init.len() + decrypted.len());
+            data.extend_from_slice(&init);
+            data.extend_from_slice(&decrypted);
+            (data.into(), reset, None)
+        }
+        (Format::Fmp4, None) => (decrypted, false, None),
+        (format, _) => {
+            let stream = match format {
+                Format::Ts => {
+                    let (stream, metadata) = demux_ts(&decrypted, number)?;
+                    if let Some(tx) = &config.metadata {
+                        metadata.into_iter().for_each(|metadata| tx.send(metadata).unwrap_or_default());
+                    }
+                    stream
                 }
-            }
-        };
-
-        let mut stream: Vec<u8> = Vec::new();
-
-        loop {
-            let packet = match ts.read_ts_packet().context("Échec: lecture d'un paquet TS") {
-                Ok(packet) => {
-                    match packet {
-                        Some(packet) => packet,
-                        None => break, // End of packets
+                _ => decrypted,
+            };
+            // Seules les trames valides sont transmises. Un segment sans trame est vidé, à moins que le flux
+            // ne soit pas ADTS
+            let (stream, summary) = adts::validate(stream);
+            let stream = match &summary {
+                Some(summary) => {
+                    if summary.skipped > 0 {
+                        eprintln!("{} octets corrompus ignorés dans le segment {number}", summary.skipped);
                     }
+                    session.adts = Some(summary.config);
+                    stream
                 }
-                Err(e) => {
-                    tx.send(Err(e)).unwrap_or_default();
-                    return;
+                None if format == Format::Ts || session.adts.is_some() => {
+                    eprintln!("Segment {number} rejeté: aucune trame ADTS valide");
+                    Bytes::new()
                 }
+                None => stream,
             };
+            (stream, false, summary)
+        }
+    };
 
-            if packet.header.pid == program_pid {
-                let data = match packet.payload {
-                    Some(payload) => match payload {
-                        TsPayload::Pes(pes) => pes.data,
-                        TsPayload::Raw(data) => data,
-                        _ => continue,
-                    },
-                    None => {
-                        tx.send(Err(anyhow!("Pas de payload"))).unwrap_or_default();
-                        return;
-                    }
-                };
-                stream.extend_from_slice(&data[..]);
-            }
+    let mut segment = Segment::new(media_segment, data);
+    segment.discontinuity |= reset || restart;
+    segment.adts = summary;
+    Ok(segment)
+}
+
+// Un segment EXT-X-GAP est omis, ou remplacé par du silence de même durée si le flux le permet
+fn gap_segment(media_segment: &MediaSegment, config: &HlsConfig, session: &Session) -> Option<Segment> {
+    match (config.gap, session.adts) {
+        (GapPolicy::Silence, Some(adts)) => adts::silence(adts, media_segment.duration.duration()).map(|data| {
+            let (data, summary) = adts::validate(data.into());
+            let mut segment = Segment::new(media_segment, data);
+            segment.adts = summary;
+            segment
+        }),
+        _ => None,
+    }
+}
+
+// Le numéro du segment qui débute le plus près de la position
+fn segment_at(media: &MediaPlaylist, position: Duration) -> usize {
+    let mut start = Duration::ZERO;
+    for (_, media_segment) in media.segments.iter() {
+        let duration = media_segment.duration.duration();
+        if start + duration / 2 > position {
+            return media_segment.number();
         }
+        start += duration;
+    }
+    media.media_sequence + media.segments.num_elements()
+}
 
-        if tx.send(Ok(stream)).is_err() {
-            return; // rx was dropped
+// Le segment qui contient la position, son début et le reste à retrancher
+fn segment_containing(media: &MediaPlaylist, position: Duration) -> (usize, Duration, Duration) {
+    let mut start = Duration::ZERO;
+    for (_, media_segment) in media.segments.iter() {
+        let duration = media_segment.duration.duration();
+        if start + duration > position {
+            return (media_segment.number(), start, position - start);
         }
+        start += duration;
     }
+    (media.media_sequence + media.segments.num_elements(), start, Duration::ZERO)
 }
 
-// Le segment est un fichier AAC encrypté
-async fn hls_on_demand2(media_url: Url, client: Client, tx: SyncSender<Message>) {
-    let response = match get(media_url.as_str(), &client).await {
-        Ok(response) => String::from_utf8(response).unwrap_or_default(),
-        Err(e) => {
-            tx.send(Err(e)).unwrap_or_default();
-            return;
+type Download = JoinHandle<Result<(Bytes, Option<Duration>)>>; // Sans durée s'il provient du cache disque
+
+struct Prefetched {
+    media_segment: MediaSegment<'static>,
+    map: Option<ExtXMap<'static>>,
+    media_url: Url,
+    download: Option<Download>, // Aucun pour un segment EXT-X-GAP
+}
+
+// Les téléchargements en cours, dans l'ordre des segments. Ils sont annulés si le pipeline s'arrête
+#[derive(Default)]
+struct Prefetch(VecDeque<Prefetched>);
+
+impl Drop for Prefetch {
+    fn drop(&mut self) {
+        for download in self.0.iter().filter_map(|prefetched| prefetched.download.as_ref()) {
+            download.abort();
         }
-    };
+    }
+}
 
-    let media = match MediaPlaylist::try_from(response.as_str()).context("Échec: validation de MediaPlayList") {
-        Ok(media) => media,
-        Err(e) => {
-            tx.send(Err(e)).unwrap_or_default();
-            return;
+// Sans validateur, le segment n'est pas mis en cache
+fn download(segment_url: Url, range: Option<Range<usize>>, key: Option<SegmentKey>, validator: Option<String>, config: &HlsConfig) -> Download {
+    let config = config.clone();
+    tokio::spawn(async move {
+        let start = Instant::now();
+        let response = get_segment(segment_url.as_str(), range.clone(), key.as_ref(), &config).await?;
+        let elapsed = start.elapsed();
+        if let Some(validator) = validator {
+            to_cache(segment_url.as_str(), range.as_ref(), &validator, &response, &config).await;
         }
-    };
+        Ok((response, Some(elapsed)))
+    })
+}
+
+// Un segment du cache disque, transmis comme un téléchargement terminé
+fn cached(data: Bytes) -> Download {
+    tokio::spawn(async move { Ok((data, None)) })
+}
 
-    let mut cache: HashMap<String, Vec<u8>> = HashMap::new();
-    let mut prec_uri = String::new(); // Problème d'URIs identiques
+async fn hls_on_demand(mut ladder: Ladder, mut media: MediaPlaylist<'static>, mut tags: Tags, config: &HlsConfig, tx: Sender<Message>) {
+    let mut session = Session::default();
+    let mut prefetch = Prefetch::default();
+    let mut prec_segment = (String::new(), None); // Problème d'URIs identiques
+    // Sauter les segments qui précèdent la position de départ
+    let (mut next, mut position, mut trim) = segment_containing(&media, config.start_position);
+    config.stats.update(|stats| stats.position = position);
 
-    for (_, media_segment) in media.segments {
-        if prec_uri == media_segment.uri().as_ref() {
-            continue; // Avec un media correctement construit, on n'aboutit jamais ici...
-        } else {
-            prec_uri = media_segment.uri().to_string();
+    loop {
+        // Télécharger jusqu'à config.concurrency segments en parallèle
+        while prefetch.0.len() < config.concurrency
+            && let Some(media_segment) = media.segments.values().find(|media_segment| media_segment.number() >= next)
+        {
+            next = media_segment.number() + 1;
+            position += media_segment.duration.duration();
+
+            // Les segments d'un même fichier se distinguent par leur plage
+            let segment = (media_segment.uri().to_string(), media_segment.byte_range);
+            if prec_segment == segment {
+                continue; // Avec un media correctement construit, on n'aboutit jamais ici...
+            } else {
+                prec_segment = segment;
+            }
+
+            let download = if tags.gaps.contains(&media_segment.number()) {
+                None
+            } else {
+                // Sauf s'il est dans le cache, la clé est obtenue avant le segment, pour le décrypter à la réception
+                let range = byte_range(media_segment.byte_range.map(|range| *range));
+                let validator = validator(ladder.url(), Some(media_segment.number()));
+                let segment = match base_or_join(ladder.url(), media_segment.uri()).context("Échec: base_or_join de l'url media segment") {
+                    Ok(segment_url) => match from_cache(segment_url.as_str(), range.as_ref(), &validator, config).await {
+                        Some(data) => Ok(cached(data)),
+                        None => segment_key(ladder.url(), media_segment.keys(), media_segment.number(), config, &mut session.keys)
+                            .await
+                            .context(format!("Échec: obtention de la clé du segment {}", media_segment.number()))
+                            .map(|key| download(segment_url, range, key, Some(validator), config)),
+                    },
+                    Err(e) => Err(e),
+                };
+                match segment {
+                    Ok(download) => Some(download),
+                    Err(e) => {
+                        tx.send(Err(e)).await.unwrap_or_default();
+                        return;
+                    }
+                }
+            };
+            prefetch.0.push_back(Prefetched {
+                media_segment: media_segment.clone(),
+                map: segment_map(&media, media_segment.number()).cloned(),
+                media_url: ladder.url().clone(),
+                download,
+            });
         }
 
-        let segment_url = match base_or_join(&media_url, media_segment.uri()).context("Échec: base_or_join de l'url media segment") {
-            Ok(url) => url,
-            Err(e) => {
-                tx.send(Err(e)).unwrap_or_default();
-                return;
+        let Prefetched {
+            media_segment,
+            map,
+            media_url,
+            download,
+        } = match prefetch.0.pop_front() {
+            Some(prefetched) => prefetched,
+            None => break,
+        };
+
+        let download = match download {
+            Some(download) => download,
+            None => {
+                if let Some(mut silence) = gap_segment(&media_segment, config, &session) {
+                    silence.trim = std::mem::take(&mut trim);
+                    if tx.send(Ok(silence)).await.is_err() {
+                        return; // rx was dropped
+                    }
+                }
+                let duration = media_segment.duration.duration();
+                config.stats.segment(&media, media_segment.number(), duration, ladder.bandwidth());
+                ladder.delivered();
+                continue;
             }
         };
-        let segment_response = match get(segment_url.as_str(), &client).await {
+        let (segment_response, elapsed) = match download
+            .await
+            .map_err(Error::new)
+            .and_then(|result| result)
+            .context(format!("Échec: obtention du segment {}", media_segment.number()))
+        {
             Ok(response) => response,
+            Err(e) if ladder.failover() => {
+                // Reprendre au même numéro de séquence sur la variante redondante
+                eprintln!("{e:#}");
+                prefetch = Prefetch::default();
+                (media, tags) = match failover_media_playlist(&mut ladder, config).await {
+                    Ok(playlist) => playlist,
+                    Err(e) => {
+                        tx.send(Err(e)).await.unwrap_or_default();
+                        return;
+                    }
+                };
+                next = media_segment.number();
+                position = media
+                    .segments
+                    .values()
+                    .take_while(|media_segment| media_segment.number() < next)
+                    .map(|media_segment| media_segment.duration.duration())
+                    .sum();
+                prec_segment = (String::new(), None);
+                continue;
+            }
             Err(e) => {
-                tx.send(Err(e)).unwrap_or_default();
+                tx.send(Err(e)).await.unwrap_or_default();
                 return;
             }
         };
+        let bytes = segment_response.len();
 
-        let keys = media_segment.keys();
+        let mut segment = match process_segment(&media_url, &media_segment, map.as_ref(), segment_response, false, config, &mut session)
+            .await
+            .context(format!("Échec: traitement du segment {}", media_segment.number()))
+        {
+            Ok(segment) => segment,
+            Err(e) => {
+                tx.send(Err(e)).await.unwrap_or_default();
+                return;
+            }
+        };
+        segment.trim = std::mem::take(&mut trim);
 
-        let decrypted = if keys.is_empty() {
-            segment_response
-        } else {
-            let key = keys.iter().find(|k| k.method == EncryptionMethod::Aes128);
+        if tx.send(Ok(segment)).await.is_err() {
+            return; // rx was dropped
+        }
+        if elapsed.is_some() {
+            config.stats.update(|stats| stats.download_time = elapsed);
+        }
+        config
+            .stats
+            .segment(&media, media_segment.number(), media_segment.duration.duration(), ladder.bandwidth());
+        ladder.delivered();
 
-            let (uri, iv) = match key {
-                Some(key) => (key.uri().as_ref(), key.iv.to_slice()),
-                None => {
-                    tx.send(Err(anyhow!("Le segment n'est pas chiffré avec AES-128"))).unwrap_or_default();
+        // Les variantes n'ont pas nécessairement les mêmes segments: poursuivre à la même position.
+        // Le débit des téléchargements parallèles est partagé, l'estimation est donc prudente.
+        // Un segment du cache ne mesure pas le débit
+        if let Some(elapsed) = elapsed
+            && ladder.sample(bytes, elapsed)
+        {
+            (media, tags) = match failover_media_playlist(&mut ladder, config).await {
+                Ok(playlist) => playlist,
+                Err(e) => {
+                    tx.send(Err(e)).await.unwrap_or_default();
                     return;
                 }
             };
+            next = segment_at(&media, position);
+            prec_segment = (String::new(), None);
+        }
+    }
+}
 
-            let key = match cache.get(uri) {
-                Some(key) => key,
-                None => match get(uri, &client).await {
-                    Ok(response) => {
-                        cache.insert(uri.to_owned(), response);
-                        cache.get(uri).unwrap()
-                    }
-                    Err(e) => {
-                        tx.send(Err(e)).unwrap_or_default();
-                        return;
-                    }
-                },
-            };
+#[derive(Debug, PartialEq)]
+enum Jump {
+    Gap(Range<usize>),
+    Restart,
+}
 
-            let iv = match iv {
-                Some(iv) => iv,
-                None => {
-                    tx.send(Err(anyhow!("Initialization Vector manquant"))).unwrap_or_default();
-                    return;
+// Suivi des segments «live» selon leur numéro de séquence (EXT-X-MEDIA-SEQUENCE)
+#[derive(Default)]
+struct LiveSequence {
+    last: Option<usize>,
+    listed: Option<(Url, Vec<(usize, String)>)>, // Les uri des segments de la playlist précédente et son url
+}
+
+impl LiveSequence {
+    // Retourne le numéro du premier segment à obtenir parmi ceux de la playlist, dans l'ordre
+    fn next(&self, url: &Url, first: usize, uris: &[&str]) -> (usize, Option<Jump>) {
+        let last = match self.last {
+            Some(last) => last,
+            None => return (first, None),
+        };
+        let len = uris.len();
+        let newest = (first + len).saturating_sub(1);
+
+        // Une playlist en retard (cache du CDN) ne liste que des segments déjà listés, sous le même uri. Si la
+        // séquence recule autrement, même de peu, l'encodeur a redémarré
+        let stale = match &self.listed {
+            Some((listed_url, listed)) if listed_url == url => match listed.first() {
+                Some((oldest, _)) if first < *oldest => {
+                    let mut common = listed
+                        .iter()
+                        .filter_map(|(number, uri)| Some((uri, uris.get(number.checked_sub(first)?)?)))
+                        .peekable();
+                    common.peek().is_some() && common.all(|(uri, listed)| uri == listed)
                 }
-            };
+                _ => true,
+            },
+            _ => newest + len >= last,
+        };
 
-            match decrypt_aes128(key, &iv, &segment_response) {
-                Ok(decrypted) => decrypted,
-                Err(e) => {
-                    tx.send(Err(e)).unwrap_or_default();
-                    return;
+        if !stale {
+            (first, Some(Jump::Restart))
+        } else if last + 1 < first {
+            (first, Some(Jump::Gap(last + 1..first)))
+        } else {
+            (last + 1, None)
+        }
+    }
+
+    fn list(&mut self, url: &Url, media: &MediaPlaylist) {
+        let uris = media
+            .segments
+            .values()
+            .map(|media_segment| (media_segment.number(), media_segment.uri().to_string()))
+            .collect();
+        self.listed = Some((url.clone(), uris));
+    }
+}
+
+// Le premier segment à obtenir selon la politique de départ. En LL-HLS, ce peut être le segment en cours
+fn live_start(media: &MediaPlaylist, tags: &Tags, policy: LiveStart, low_latency: bool) -> usize {
+    let first = media.media_sequence;
+    let newest = (first + media.segments.num_elements()).saturating_sub(1);
+    match policy {
+        LiveStart::Oldest => first,
+        LiveStart::Edge(n) => newest.saturating_sub(n).max(first),
+        LiveStart::HoldBack => {
+            // Reculer depuis la fin jusqu'à couvrir le délai. En LL-HLS, la fin est la dernière partie du
+            // segment en cours et le délai est PART-HOLD-BACK
+            let (hold_back, mut held) = match tags.part_target.filter(|_| low_latency) {
+                Some(part_target) => (
+                    tags.part_hold_back.unwrap_or(part_target * 3),
+                    tags.parts
+                        .get(&(newest + 1))
+                        .map_or(Duration::ZERO, |parts| parts.iter().map(|part| part.duration).sum()),
+                ),
+                None => (tags.hold_back.unwrap_or(media.target_duration * 3), Duration::ZERO),
+            };
+            let mut start = newest + 1;
+            for (_, media_segment) in media.segments.iter().rev() {
+                if held >= hold_back {
+                    break;
                 }
+                held += media_segment.duration.duration();
+                start = media_segment.number();
             }
-        };
+            start.max(first)
+        }
+    }
+}
 
-        if tx.send(Ok(decrypted)).is_err() {
-            return; // rx was dropped
+// La durée des segments de la playlist à partir de next
+fn latency(media: &MediaPlaylist, next: usize) -> Duration {
+    media
+        .segments
+        .values()
+        .filter(|media_segment| media_segment.number() >= next)
+        .map(|media_segment| media_segment.duration.duration())
+        .sum()
+}
+
+// Un segment, ou une partie de segment (EXT-X-PART), à obtenir d'une playlist «live»
+struct LiveItem {
+    media_segment: MediaSegment<'static>,
+    part: Option<usize>,
+    last: bool, // La dernière partie d'un segment complet
+    gap: bool,
+}
+
+// Les parties d'un segment à partir de from. Elles n'ont que le numéro et les clés de leur segment
+fn part_items(number: usize, parts: &[Part], from: usize, complete: bool, keys: &[ExtXKey<'static>]) -> Vec<LiveItem> {
+    parts
+        .iter()
+        .enumerate()
+        .skip(from)
+        .map(|(index, part)| {
+            let mut builder = MediaSegment::builder();
+            builder
+                .number(Some(number))
+                .duration(part.duration)
+                .uri(part.uri.clone())
+                .keys(keys.to_vec())
+                .has_discontinuity(part.discontinuity);
+            if let Some(range) = &part.range {
+                builder.byte_range(ByteRange::from(range.clone()));
+            }
+            LiveItem {
+                media_segment: builder.build().expect("Échec: création de la partie"),
+                part: Some(index),
+                last: complete && index + 1 == parts.len(),
+                gap: part.gap,
+            }
+        })
+        .collect()
+}
+
+// Les segments à obtenir à partir de next. En LL-HLS, un segment entamé (partial) se poursuit partie par
+// partie, puis viennent les parties du segment en cours de production
+fn live_items(media: &MediaPlaylist<'static>, tags: &Tags, next: usize, partial: Option<(usize, usize)>, low_latency: bool) -> Vec<LiveItem> {
+    let mut items = Vec::new();
+    for media_segment in media.segments.values().filter(|media_segment| media_segment.number() >= next) {
+        let number = media_segment.number();
+        match partial {
+            Some((msn, from)) if low_latency && msn == number => {
+                let parts = tags.parts.get(&number).map_or(&[][..], Vec::as_slice);
+                items.extend(part_items(number, parts, from, true, &media_segment.keys));
+            }
+            _ => items.push(LiveItem {
+                media_segment: media_segment.clone(),
+                part: None,
+                last: false,
+                gap: tags.gaps.contains(&number),
+            }),
         }
     }
+
+    let in_progress = media.media_sequence + media.segments.num_elements();
+    if low_latency
+        && in_progress >= next
+        && let Some(parts) = tags.parts.get(&in_progress)
+    {
+        let from = partial.filter(|(msn, _)| *msn == in_progress).map_or(0, |(_, from)| from);
+        let keys = media
+            .segments
+            .values()
+            .last()
+            .map_or(&[][..], |media_segment| media_segment.keys.as_slice());
+        items.extend(part_items(in_progress, parts, from, false, keys));
+    }
+    items
 }
 
-async fn hls_live(media_url: Url, client: Client, tx: SyncSender<Message>) {
-    let mut sequence = String::new();
+// La partie annoncée par EXT-X-PRELOAD-HINT, obtenue avant d'être listée. Annulée si elle ne sert pas
+struct Preload {
+    url: Url,
+    range: Option<Range<usize>>,
+    download: Download,
+}
+
+impl Drop for Preload {
+    fn drop(&mut self) {
+        self.download.abort();
+    }
+}
+
+async fn hls_live(mut ladder: Ladder, mut media: MediaPlaylist<'static>, mut tags: Tags, config: &HlsConfig, tx: Sender<Message>) {
+    let mut session = Session::default();
+    let mut sequence = LiveSequence::default();
+    let mut restart = false; // Le prochain segment est une discontinuité
+    let mut partial = None; // Le segment obtenu partie par partie et sa prochaine partie
+    let mut preload: Option<Preload> = None;
+    let mut cache = PlaylistCache::default();
     loop {
         let start = Instant::now();
         let mut changed = false;
+        let mut failed = false; // Passer à une variante redondante
+        // LL-HLS seulement si la playlist a des parties
+        let low_latency = config.low_latency && tags.part_target.is_some();
 
-        let response = match get(media_url.as_str(), &client).await {
-            Ok(response) => String::from_utf8(response).unwrap_or_default(),
-            Err(e) => {
-                tx.send(Err(e)).unwrap_or_default();
-                return;
-            }
-        };
+        // La variante peut changer en cours de route, mais les segments de cette playlist relèvent de son url
+        let media_url = ladder.url().clone();
 
-        let media = match MediaPlaylist::try_from(response.as_str()).context("Échec: validation de MediaPlayList") {
-            Ok(media) => media,
-            Err(e) => {
-                tx.send(Err(e)).unwrap_or_default();
-                return;
-            }
-        };
+        // Un segment entamé est terminé lorsque la playlist le liste sans autre partie
+        let in_progress = media.media_sequence + media.segments.num_elements();
+        if let Some((msn, from)) = partial
+            && msn < in_progress
+            && from >= tags.parts.get(&msn).map_or(0, Vec::len)
+        {
+            sequence.last = Some(msn);
+            partial = None;
+        }
 
-        for (_, media_segment) in media.segments {
-            let uri = media_segment.uri().as_ref();
-            if sequence.as_str() < uri {
-                let segment_url = match base_or_join(&media_url, uri).context("Échec: base_or_join de l'url media segment") {
-                    Ok(url) => url,
-                    Err(e) => {
-                        tx.send(Err(e)).unwrap_or_default();
-                        return;
+        let next = match partial {
+            Some((msn, _)) => msn,
+            None => {
+                let uris = media
+                    .segments
+                    .values()
+                    .map(|media_segment| media_segment.uri().as_ref())
+                    .collect::<Vec<&str>>();
+                let next = match sequence.next(&media_url, media.media_sequence, &uris) {
+                    (next, Some(Jump::Gap(gap))) => {
+                        eprintln!("Segments {} à {} manquants dans {}", gap.start, gap.end - 1, media_url.as_str());
+                        config.stats.update(|stats| stats.missing += gap.len());
+                        restart = true;
+                        next
                     }
-                };
-                let segment_response = match get(segment_url.as_str(), &client).await {
-                    Ok(response) => response,
-                    Err(e) => {
-                        tx.send(Err(e)).unwrap_or_default();
-                        return;
+                    (next, Some(Jump::Restart)) => {
+                        eprintln!("Redémarrage de la séquence à {} dans {}", media.media_sequence, media_url.as_str());
+                        restart = true;
+                        next
                     }
+                    (next, None) => next,
                 };
-                if tx.send(Ok(segment_response)).is_err() {
+
+                // Au premier passage, ou si le retard s'accumule, reprendre au point de départ. Les segments
+                // sautés sont une discontinuité
+                match (sequence.last, config.max_latency) {
+                    (None, _) => live_start(&media, &tags, config.live_start, low_latency),
+                    (Some(_), Some(max_latency)) if latency(&media, next) > max_latency => {
+                        eprintln!("Retard de {:.0} s sur le direct: resynchronisation", latency(&media, next).as_secs_f64());
+                        restart = true;
+                        live_start(&media, &tags, config.live_start, low_latency)
+                    }
+                    _ => next,
+                }
+            }
+        };
+        sequence.list(&media_url, &media);
+
+        for LiveItem {
+            media_segment,
+            part,
+            last,
+            gap,
+        } in live_items(&media, &tags, next, partial, low_latency)
+        {
+            let number = media_segment.number();
+            changed = true;
+            let resume = match part {
+                Some(part) if !last => (sequence.last, Some((number, part + 1))),
+                _ => (Some(number), None),
+            };
+            if gap {
+                (sequence.last, partial) = resume;
+                if let Some(silence) = gap_segment(&media_segment, config, &session)
+                    && tx.send(Ok(silence)).await.is_err()
+                {
                     return; // rx was dropped
                 }
-                changed = true;
-                sequence = uri.to_owned();
+                config
+                    .stats
+                    .segment(&media, number, media_segment.duration.duration(), ladder.bandwidth());
+                ladder.delivered();
+                continue;
             }
+
+            let segment_url = match base_or_join(&media_url, media_segment.uri()).context("Échec: base_or_join de l'url media segment") {
+                Ok(url) => url,
+                Err(e) => {
+                    tx.send(Err(e)).await.unwrap_or_default();
+                    return;
+                }
+            };
+            let range = byte_range(media_segment.byte_range.map(|range| *range));
+            let key = match segment_key(&media_url, media_segment.keys(), number, config, &mut session.keys)
+                .await
+                .context(format!("Échec: obtention de la clé du segment {number}"))
+            {
+                Ok(key) => key,
+                Err(e) => {
+                    tx.send(Err(e)).await.unwrap_or_default();
+                    return;
+                }
+            };
+            // La partie préchargée attend sa production: sa durée ne mesure pas le débit. Elle n'est pas décryptée
+            let preloaded = preload
+                .take()
+                .filter(|preload| key.is_none() && preload.url == segment_url && preload.range == range);
+            let sample = preloaded.is_none();
+            let response = match preloaded {
+                Some(mut preload) => (&mut preload.download).await.map_err(Error::new).and_then(|result| result),
+                None => {
+                    let segment_start = Instant::now();
+                    get_segment(segment_url.as_str(), range, key.as_ref(), config)
+                        .await
+                        .map(|response| (response, Some(segment_start.elapsed())))
+                }
+            };
+            let (segment_response, elapsed) = match response.context(format!("Échec: obtention du segment {number}")) {
+                Ok(response) => response,
+                Err(e) if ladder.failover() => {
+                    // Le segment sera obtenu de la variante redondante, au même numéro de séquence
+                    eprintln!("{e:#}");
+                    failed = true;
+                    break;
+                }
+                Err(e) => {
+                    tx.send(Err(e)).await.unwrap_or_default();
+                    return;
+                }
+            };
+            if sample && let Some(elapsed) = elapsed {
+                ladder.sample(segment_response.len(), elapsed);
+            }
+            let mut segment = match process_segment(
+                &media_url,
+                &media_segment,
+                segment_map(&media, number),
+                segment_response,
+                restart,
+                config,
+                &mut session,
+            )
+            .await
+            .context(format!("Échec: traitement du segment {number}"))
+            {
+                Ok(segment) => segment,
+                Err(e) => {
+                    tx.send(Err(e)).await.unwrap_or_default();
+                    return;
+                }
+            };
+            // Les horodatages d'un encodeur redémarré repartent de zéro, et des segments manquants laissent un trou:
+            // process_segment en a fait une discontinuité
+            segment.part = part;
+            restart = false;
+            if tx.send(Ok(segment)).await.is_err() {
+                return; // rx was dropped
+            }
+            (sequence.last, partial) = resume;
+            config.stats.update(|stats| stats.download_time = elapsed);
+            config
+                .stats
+                .segment(&media, number, media_segment.duration.duration(), ladder.bandwidth());
+            ladder.delivered();
+        }
+
+        // Obtenir la prochaine partie pendant le rechargement de la playlist
+        if low_latency
+            && !failed
+            && let Some((uri, range)) = &tags.preload_hint
+            && let Ok(url) = base_or_join(&media_url, uri)
+            && preload.as_ref().is_none_or(|preload| preload.url != url || preload.range != *range)
+        {
+            preload = Some(Preload {
+                download: download(url.clone(), range.clone(), None, None, config),
+                url,
+                range: range.clone(),
+            });
         }
-        let delay = match changed {
-            true => media.target_duration.saturating_sub(start.elapsed()),
-            false => media.target_duration / 2,
+
+        // Les variantes d'un flux «live» partagent les mêmes numéros de séquence
+        let mut reload_url = match partial {
+            _ if failed => ladder.url().clone(),
+            _ if !(low_latency && tags.can_block_reload) => {
+                let delay = match (tags.part_target.filter(|_| low_latency), changed) {
+                    (Some(part_target), _) => part_target,
+                    (None, true) => media.target_duration.saturating_sub(start.elapsed()),
+                    (None, false) => media.target_duration / 2,
+                };
+                tokio::time::sleep(delay).await;
+                ladder.url().clone()
+            }
+            Some((msn, part)) => blocking_url(ladder.url(), msn, part, tags.can_skip),
+            None => blocking_url(ladder.url(), sequence.last.map_or(in_progress, |last| last + 1), 0, tags.can_skip),
+        };
+        (media, tags) = loop {
+            match reload_media_playlist(&reload_url, &mut cache, config).await {
+                Ok(playlist) => {
+                    // Une playlist obtenue, même inchangée (304), hors d'une relève pour un segment
+                    if !failed {
+                        ladder.delivered();
+                    }
+                    break playlist;
+                }
+                Err(e) if ladder.failover() => {
+                    eprintln!("{e:#}");
+                    reload_url = ladder.url().clone();
+                }
+                Err(e) => {
+                    tx.send(Err(e)).await.unwrap_or_default();
+                    return;
+                }
+            }
         };
-        thread::sleep(delay);
     }
 }
 
 // HTTP Live Streaming (HLS)
-async fn handle_hls(master_url: Url, client: Client, tx: SyncSender<Message>) {
-    let response = match get(master_url.as_str(), &client).await {
-        Ok(response) => String::from_utf8(response).unwrap_or_default(),
+async fn handle_hls(master_url: Url, config: &HlsConfig, tx: Sender<Message>) {
+    let response = match get(master_url.as_str(), config).await {
+        Ok(response) => std::str::from_utf8(&response).unwrap_or_default().to_owned(),
         Err(e) => {
-            tx.send(Err(e)).unwrap_or_default();
+            tx.send(Err(e)).await.unwrap_or_default();
             return;
         }
     };
 
-    let master = match MasterPlaylist::try_from(response.as_str()).context("Échec: validation de MasterPlayList") {
+    let master = match MasterPlaylist::try_from(response.as_str()) {
         Ok(master) => master,
         Err(e) => {
-            tx.send(Err(e)).unwrap_or_default();
+            let e = HlsError::Playlist(format!("Échec: validation de MasterPlayList: {e}"));
+            tx.send(Err(e.into())).await.unwrap_or_default();
             return;
         }
     };
 
-    // Selectionner le flux mp4a.40.2 (AAC-LC) ayant le «bitrate» le plus élevé
-    let vs = match master
-        .variant_streams
-        .iter()
-        .filter(|vs| match vs.codecs() {
-            Some(codecs) if codecs.len() == 1 => codecs[0] == "mp4a.40.2",
-            _ => false,
-        })
-        .max_by_key(|vs| vs.bandwidth())
-    {
+    // Selectionner le flux audio selon les codecs préférés et la politique de sélection
+    let candidates = variant::candidates(&master.variant_streams, &config.codecs, config.variant);
+    let vs = match variant::select(&candidates, config.variant) {
         Some(vs) => vs,
         None => {
-            tx.send(Err(anyhow!("Pas de stream mp4a.40.2 dans {}", master_url.as_str())))
+            tx.send(Err(HlsError::NoSuitableVariant(format!(
+                "Pas de stream {} dans {}",
+                config.codecs.join(", "),
+                master_url.as_str()
+            ))
+            .into()))
+                .await
                 .unwrap_or_default();
             return;
         }
     };
 
-    let media_url = match vs {
-        VariantStream::ExtXStreamInf { uri, .. } => uri,
-        _ => {
-            tx.send(Err(anyhow!("ExtXIFrameInf manquant"))).unwrap_or_default();
-            return;
-        }
+    // La piste audio du groupe de la variante. Son URI remplace alors celui de la variante, et la
+    // commutation adaptative, qui ne changerait pas de piste, est sans objet
+    let renditions = rendition::renditions(&master.media);
+    let (group, rendition) = match vs {
+        VariantStream::ExtXStreamInf { audio: Some(group_id), .. } => (
+            renditions.iter().filter(|rendition| rendition.group_id == *group_id).cloned().collect(),
+            rendition::select(&renditions, group_id, &config.rendition).cloned(),
+        ),
+        _ => (Vec::new(), None),
     };
+    config.stats.update(|stats| {
+        stats.renditions = group;
+        stats.rendition = rendition.clone();
+    });
+
+    let rendition_uri = rendition.as_ref().and_then(|rendition| rendition.uri.as_ref());
+    let adaptive = config.adaptive && rendition_uri.is_none();
 
-    let url = match base_or_join(&master_url, media_url).context("Échec: base_or_join de l'url MediaPlaylist") {
-        Ok(url) => url,
+    // Les variantes de «bitrate» inférieur servent à la commutation adaptative, celles de même «bitrate»
+    // à la relève. Une variante redondante a sa propre piste audio, choisie de la même façon
+    let mut variants = Vec::new();
+    for candidate in candidates.iter().filter(|candidate| candidate.bandwidth() <= vs.bandwidth()) {
+        if let VariantStream::ExtXStreamInf { uri, audio, .. } = candidate
+            && (adaptive || candidate.bandwidth() == vs.bandwidth())
+        {
+            let uri = match audio {
+                Some(group_id) if rendition_uri.is_some() => rendition::select(&renditions, group_id, &config.rendition)
+                    .and_then(|rendition| rendition.uri.as_deref())
+                    .unwrap_or(uri),
+                _ => uri.as_ref(),
+            };
+            match base_or_join(&master_url, uri).context("Échec: base_or_join de l'url MediaPlaylist") {
+                Ok(url) => variants.push((candidate.bandwidth(), url)),
+                Err(e) => {
+                    tx.send(Err(e)).await.unwrap_or_default();
+                    return;
+                }
+            }
+        }
+    }
+    let mut ladder = Ladder::new(variants, adaptive);
+
+    let (media, tags) = match failover_media_playlist(&mut ladder, config).await {
+        Ok(playlist) => playlist,
         Err(e) => {
-            tx.send(Err(e)).unwrap_or_default();
+            tx.send(Err(e)).await.unwrap_or_default();
             return;
         }
     };
 
-    if master.has_independent_segments {
-        hls_on_demand2(url, client, tx).await;
-    } else if media_url.starts_with("https://rcavliveaudio.akamaized.net") {
-        hls_live(url, client, tx).await
+    if is_live(&media) {
+        // Les segments d'un flux «live» ne sont pas réécoutés: pas de cache disque
+        let config = HlsConfig {
+            cache: None,
+            ..config.clone()
+        };
+        hls_live(ladder, media, tags, &config, tx).await
     } else {
-        hls_on_demand1(url, client, tx).await;
+        hls_on_demand(ladder, media, tags, config, tx).await;
     }
 }
 
-pub fn start(url: &str) -> Result<Receiver<Message>> {
-    let master_url = Url::try_from(url).context("Échec: validation de l'url MasterPlaylist")?;
-    let client = Client::builder().timeout(Duration::from_secs(TIME_OUT)).build()?;
-    let (tx, rx) = sync_channel::<Message>(BOUND);
-    thread::spawn(move || {
-        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
-        rt.block_on(handle_hls(master_url, client, tx));
+// Les segments du flux, obtenus par une tâche du runtime de l'appelant. La tâche est annulée si le flux est abandonné
+pub struct HlsStream {
+    rx: mpsc::Receiver<Message>,
+    task: JoinHandle<()>,
+}
+
+impl Stream for HlsStream {
+    type Item = Message;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+impl Drop for HlsStream {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// Échoue hors du contexte d'un runtime tokio
+pub fn stream(url: &str, config: HlsConfig) -> Result<HlsStream> {
+    let runtime = tokio::runtime::Handle::try_current().context("Échec: stream doit être appelé dans un runtime tokio")?;
+    let master_url = Url::try_from(url).map_err(|e| HlsError::Playlist(format!("Échec: validation de l'url MasterPlaylist: {e}")))?;
+    let (tx, rx) = mpsc::channel::<Message>(config.bound.max(1));
+    let task = runtime.spawn(async move { handle_hls(master_url, &config, tx).await });
+
+    Ok(HlsStream { rx, task })
+}
+
+// Le thread qui relaie le flux d'un appelant synchrone
+pub struct HlsHandle {
+    abort: AbortHandle,
+    thread: thread::JoinHandle<()>,
+}
+
+impl HlsHandle {
+    // Arrête les téléchargements sur-le-champ et libère les connexions. Le récepteur reçoit ensuite les segments
+    // déjà obtenus, puis HlsError::Cancelled
+    pub fn cancel(&self) {
+        self.abort.abort();
+    }
+
+    // Attendre la fin du thread. Un segment en attente doit d'abord être reçu, ou le récepteur abandonné
+    pub fn join(self) -> Result<()> {
+        self.thread.join().map_err(|_| anyhow!("Le thread de téléchargement a paniqué"))
+    }
+}
+
+pub fn start(url: &str) -> Result<(Receiver<Message>, HlsHandle)> {
+    start_with(url, HlsConfig::builder().build()?)
+}
+
+// Un flux sur demande à partir de la position. Le premier segment indique dans trim ce qu'il faut en retrancher
+pub fn start_at(url: &str, position: Duration) -> Result<(Receiver<Message>, HlsHandle)> {
+    start_with(url, HlsConfig::builder().start_position(position).build()?)
+}
+
+// Pour un appelant synchrone: le flux est relayé par un thread qui a son propre runtime
+pub fn start_with(url: &str, config: HlsConfig) -> Result<(Receiver<Message>, HlsHandle)> {
+    // Le pipeline tourne sur le worker pendant que le thread attend le récepteur
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .context("Échec: création du runtime")?;
+    let mut hls = {
+        let _guard = rt.enter();
+        stream(url, config)?
+    };
+    let abort = hls.task.abort_handle();
+
+    let (tx, rx) = sync_channel::<Message>(1);
+    let thread = thread::spawn(move || {
+        rt.block_on(async move {
+            while let Some(message) = hls.next().await {
+                if tx.send(message).is_err() {
+                    return; // rx was dropped
+                }
+            }
+            if let Err(e) = (&mut hls.task).await
+                && e.is_cancelled()
+            {
+                tx.send(Err(HlsError::Cancelled.into())).unwrap_or_default(); // Après le segment en attente
+            }
+        });
     });
 
-    Ok(rx)
+    Ok((rx, HlsHandle { abort, thread }))
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{decrypt_aes128, start};
+    use super::*;
 
     #[test]
     fn ohdio() {
-        let rx = start("Insérer un url master.m3u8 Ohdio validé").unwrap();
+        let (rx, _) = start("Insérer un url master.m3u8 Ohdio validé").unwrap();
         match rx.recv() {
             Ok(s) => match s {
-                Ok(message) => assert!(message.len() > 0),
+                Ok(segment) => assert!(segment.data.len() > 0),
                 Err(e) => {
                     println!("{e:?}");
                     assert!(false);
@@ -499,7 +1426,758 @@ mod tests {
             0xDA, 0x52, 0xF9, 0x7B, 0xAB, 0xAE, 0x0A, 0x79, 0x7F, 0x1C, 0x11, 0xEC, 0xB2, 0x09, 0x9F, 0xB0,
         ];
 
-        let result = decrypt_aes128(&key, &iv, &data).unwrap();
+        let mut decryptor = CbcDecryptor::new(&SegmentKey::new(key, iv).unwrap());
+        let mut result = Vec::new();
+        decryptor.update(&data, &mut result);
+        decryptor.finish(&mut result).unwrap();
         assert_eq!(String::from_utf8(result).unwrap(), "DOH!");
     }
+
+    #[test]
+    fn mode() {
+        let playlist = |tags: &str| format!("#EXTM3U\n#EXT-X-TARGETDURATION:10\n{tags}#EXTINF:10,\nseg1.aac\n");
+        let live = playlist("");
+        let vod = playlist("#EXT-X-PLAYLIST-TYPE:VOD\n");
+        let event = playlist("#EXT-X-PLAYLIST-TYPE:EVENT\n");
+        let event_end = format!("{event}#EXT-X-ENDLIST\n");
+        let end = format!("{live}#EXT-X-ENDLIST\n");
+
+        assert!(is_live(&MediaPlaylist::try_from(live.as_str()).unwrap()));
+        assert!(!is_live(&MediaPlaylist::try_from(vod.as_str()).unwrap()));
+        assert!(is_live(&MediaPlaylist::try_from(event.as_str()).unwrap()));
+        assert!(!is_live(&MediaPlaylist::try_from(event_end.as_str()).unwrap()));
+        assert!(!is_live(&MediaPlaylist::try_from(end.as_str()).unwrap()));
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!(Format::from_uri("https://cdn/a/seg1.ts?token=b.c"), Some(Format::Ts));
+        assert_eq!(Format::from_uri("seg1.AAC"), Some(Format::Aac));
+        assert_eq!(Format::from_uri("segment/1234"), None);
+
+        let mut ts = vec![0u8; 376];
+        ts[0] = 0x47;
+        ts[188] = 0x47;
+        assert_eq!(Format::sniff(&ts), Format::Ts);
+        assert_eq!(Format::sniff(&[0xFF, 0xF1, 0x50, 0x80]), Format::Aac);
+
+        assert_eq!(Format::from_uri("audio/seg1.m4s"), Some(Format::Fmp4));
+        assert_eq!(Format::sniff(b"\x00\x00\x00\x18stypmsdh"), Format::Fmp4);
+        assert_eq!(Format::sniff(b"\x00\x00\x00\x10moof"), Format::Fmp4);
+    }
+
+    #[test]
+    fn live_sequence() {
+        let url = Url::parse("https://cdn/live.m3u8").unwrap();
+        let playlist = |first: usize, len: usize, name: &str| {
+            let mut playlist = format!("#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXT-X-MEDIA-SEQUENCE:{first}\n");
+            for number in first..first + len {
+                playlist.push_str(&format!("#EXTINF:4,\n{name}{number}.aac\n"));
+            }
+            MediaPlaylist::try_from(playlist.as_str()).unwrap().into_owned()
+        };
+        let next = |sequence: &LiveSequence, media: &MediaPlaylist| {
+            let uris = media
+                .segments
+                .values()
+                .map(|media_segment| media_segment.uri().as_ref())
+                .collect::<Vec<&str>>();
+            sequence.next(&url, media.media_sequence, &uris)
+        };
+
+        let mut sequence = LiveSequence::default();
+        assert_eq!(next(&sequence, &playlist(998, 3, "seg")), (998, None));
+
+        // seg999 -> seg1000 n'est pas un problème
+        sequence.last = Some(1000);
+        assert_eq!(next(&sequence, &playlist(999, 3, "seg")), (1001, None));
+        assert_eq!(next(&sequence, &playlist(998, 3, "seg")), (1001, None));
+
+        sequence.last = Some(1001);
+        assert_eq!(next(&sequence, &playlist(1005, 3, "seg")), (1005, Some(Jump::Gap(1002..1005))));
+
+        sequence.last = Some(1007);
+        assert_eq!(next(&sequence, &playlist(0, 3, "seg")), (0, Some(Jump::Restart)));
+
+        // Une playlist en retard liste les mêmes segments que la précédente
+        sequence.last = Some(5);
+        sequence.list(&url, &playlist(1, 5, "seg"));
+        assert_eq!(next(&sequence, &playlist(0, 5, "seg")), (6, None));
+
+        // L'encodeur a redémarré peu après le début: la séquence recule avec d'autres segments
+        assert_eq!(next(&sequence, &playlist(0, 5, "restart")), (0, Some(Jump::Restart)));
+        assert_eq!(next(&sequence, &playlist(0, 6, "restart")), (0, Some(Jump::Restart)));
+
+        // Les segments d'une autre variante ne sont pas comparés
+        let other = Url::parse("https://cdn/backup.m3u8").unwrap();
+        let uris = ["restart0.aac", "restart1.aac", "restart2.aac", "restart3.aac", "restart4.aac"];
+        assert_eq!(sequence.next(&other, 0, &uris), (6, None));
+    }
+
+    #[test]
+    fn live_edge() {
+        let playlist = concat!(
+            "#EXTM3U\n",
+            "#EXT-X-TARGETDURATION:4\n",
+            "#EXT-X-MEDIA-SEQUENCE:100\n",
+            "#EXTINF:4,\n",
+            "seg100.aac\n",
+            "#EXTINF:4,\n",
+            "seg101.aac\n",
+            "#EXTINF:4,\n",
+            "seg102.aac\n",
+            "#EXTINF:4,\n",
+            "seg103.aac\n",
+            "#EXTINF:4,\n",
+            "seg104.aac\n",
+        );
+        let media = MediaPlaylist::try_from(playlist).unwrap();
+        let mut tags = Tags::parse(playlist, media.media_sequence);
+        assert_eq!(live_start(&media, &tags, LiveStart::Oldest, false), 100);
+        assert_eq!(live_start(&media, &tags, LiveStart::Edge(0), false), 104);
+        assert_eq!(live_start(&media, &tags, LiveStart::Edge(10), false), 100);
+        // Trois fois la durée cible
+        assert_eq!(live_start(&media, &tags, LiveStart::HoldBack, false), 102);
+        tags.hold_back = Some(Duration::from_secs(6));
+        assert_eq!(live_start(&media, &tags, LiveStart::HoldBack, false), 103);
+
+        assert_eq!(latency(&media, 102), Duration::from_secs(12));
+        assert_eq!(latency(&media, 105), Duration::ZERO);
+    }
+
+    #[test]
+    fn low_latency() {
+        let playlist = concat!(
+            "#EXTM3U\n",
+            "#EXT-X-TARGETDURATION:2\n",
+            "#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK=1.5\n",
+            "#EXT-X-PART-INF:PART-TARGET=0.5\n",
+            "#EXT-X-MEDIA-SEQUENCE:10\n",
+            "#EXTINF:2,\n",
+            "seg10.aac\n",
+            "#EXT-X-PART:DURATION=0.5,URI=\"part11.0.aac\"\n",
+            "#EXT-X-PART:DURATION=0.5,URI=\"part11.1.aac\"\n",
+            "#EXT-X-PART:DURATION=0.5,URI=\"part11.2.aac\"\n",
+            "#EXT-X-PART:DURATION=0.5,URI=\"part11.3.aac\"\n",
+            "#EXTINF:2,\n",
+            "seg11.aac\n",
+            "#EXT-X-PART:DURATION=0.5,URI=\"part12.0.aac\"\n",
+            "#EXT-X-PART:DURATION=0.5,URI=\"part12.1.aac\"\n",
+            "#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"part12.2.aac\"\n",
+        );
+        let (media, tags) = parse_media_playlist(playlist).unwrap();
+        assert_eq!(media.segments.num_elements(), 2);
+
+        // Un segment et demi avant la dernière partie
+        assert_eq!(live_start(&media, &tags, LiveStart::HoldBack, true), 11);
+        assert_eq!(live_start(&media, &tags, LiveStart::HoldBack, false), 10);
+
+        let uris = |items: Vec<LiveItem>| {
+            items
+                .iter()
+                .map(|item| (item.media_segment.uri().to_string(), item.media_segment.number(), item.part, item.last))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            uris(live_items(&media, &tags, 11, Some((11, 2)), true)),
+            [
+                ("part11.2.aac".to_owned(), 11, Some(2), false),
+                ("part11.3.aac".to_owned(), 11, Some(3), true),
+                ("part12.0.aac".to_owned(), 12, Some(0), false),
+                ("part12.1.aac".to_owned(), 12, Some(1), false),
+            ]
+        );
+        assert_eq!(
+            uris(live_items(&media, &tags, 12, Some((12, 1)), true)),
+            [("part12.1.aac".to_owned(), 12, Some(1), false)]
+        );
+        // Sans LL-HLS, les parties sont ignorées
+        assert_eq!(
+            uris(live_items(&media, &tags, 11, None, false)),
+            [("seg11.aac".to_owned(), 11, None, false)]
+        );
+
+        let url = Url::parse("http://localhost/live.m3u8").unwrap();
+        assert_eq!(
+            blocking_url(&url, 12, 2, true).as_str(),
+            "http://localhost/live.m3u8?_HLS_msn=12&_HLS_part=2&_HLS_skip=YES"
+        );
+    }
+
+    #[test]
+    fn iv() {
+        let playlist = concat!(
+            "#EXTM3U\n",
+            "#EXT-X-TARGETDURATION:10\n",
+            "#EXT-X-MEDIA-SEQUENCE:7\n",
+            "#EXT-X-KEY:METHOD=AES-128,URI=\"cle.key\"\n",
+            "#EXTINF:10,\n",
+            "seg7.ts\n",
+            "#EXT-X-KEY:METHOD=AES-128,URI=\"../cle2.key\",IV=0x000102030405060708090A0B0C0D0E0F\n",
+            "#EXTINF:10,\n",
+            "seg8.ts\n",
+            "#EXT-X-ENDLIST\n"
+        );
+        let media = MediaPlaylist::try_from(playlist).unwrap();
+        let segments = media.segments.values().collect::<Vec<_>>();
+
+        let key = segments[0].keys()[0];
+        assert_eq!(segment_iv(key, segments[0].number()), 7u128.to_be_bytes());
+
+        let key = segments[1].keys()[0];
+        assert_eq!(key.uri(), "../cle2.key");
+        assert_eq!(
+            segment_iv(key, segments[1].number()),
+            *b"\x00\x01\x02\x03\x04\x05\x06\x07\x08\x09\x0A\x0B\x0C\x0D\x0E\x0F"
+        );
+    }
+
+    #[test]
+    fn config() {
+        assert!(HlsConfig::builder().header("Referer", "https://ici.radio-canada.ca").build().is_ok());
+        assert!(HlsConfig::builder().header("Mauvais entête", "valeur").build().is_err());
+        assert!(HlsConfig::builder().proxy("http://proxy:3128").user_agent("odieux").build().is_ok());
+        assert_eq!(HlsConfig::builder().concurrency(0).build().unwrap().concurrency, 1);
+        assert_eq!(HlsConfig::default().concurrency, 1);
+    }
+
+    #[test]
+    fn position() {
+        let playlist = concat!(
+            "#EXTM3U\n",
+            "#EXT-X-TARGETDURATION:10\n",
+            "#EXT-X-MEDIA-SEQUENCE:1\n",
+            "#EXTINF:9.98,\n",
+            "seg1.aac\n",
+            "#EXTINF:10.03,\n",
+            "seg2.aac\n",
+            "#EXTINF:10,\n",
+            "seg3.aac\n",
+            "#EXT-X-ENDLIST\n"
+        );
+        let media = MediaPlaylist::try_from(playlist).unwrap();
+        assert_eq!(segment_at(&media, Duration::ZERO), 1);
+        assert_eq!(segment_at(&media, Duration::from_secs(10)), 2);
+        assert_eq!(segment_at(&media, Duration::from_secs(20)), 3);
+        assert_eq!(segment_at(&media, Duration::from_secs(30)), 4);
+
+        let secs = Duration::from_secs_f64;
+        assert_eq!(segment_containing(&media, Duration::ZERO), (1, Duration::ZERO, Duration::ZERO));
+        let (number, start, trim) = segment_containing(&media, secs(15.0));
+        assert_eq!((number, start), (2, secs(9.98)));
+        assert!((trim.as_secs_f64() - 5.02).abs() < 1e-6);
+        assert_eq!(segment_containing(&media, secs(40.0)), (4, secs(30.01), Duration::ZERO));
+    }
+
+    #[test]
+    fn start_position() {
+        let playlist = concat!(
+            "#EXTM3U\n",
+            "#EXT-X-TARGETDURATION:10\n",
+            "#EXTINF:10,\n",
+            "seg0.aac\n",
+            "#EXTINF:10,\n",
+            "seg1.aac\n",
+            "#EXTINF:10,\n",
+            "seg2.aac\n",
+            "#EXT-X-ENDLIST\n"
+        );
+        // Le premier segment n'est jamais demandé
+        let url = serve(vec![
+            response("200 OK", "", MASTER),
+            response("200 OK", "", playlist),
+            response("200 OK", "", "seg1"),
+            response("200 OK", "", "seg2"),
+        ]);
+        let (rx, _) = start_at(&url, Duration::from_secs(15)).unwrap();
+        let segments = rx.iter().map(Result::unwrap).collect::<Vec<_>>();
+        let segments = segments
+            .iter()
+            .map(|segment| (segment.sequence, &segment.data[..], segment.trim))
+            .collect::<Vec<_>>();
+        assert_eq!(segments, [(1, &b"seg1"[..], Duration::from_secs(5)), (2, b"seg2", Duration::ZERO)]);
+    }
+
+    #[test]
+    fn failover() {
+        let playlist = concat!(
+            "#EXTM3U\n",
+            "#EXT-X-TARGETDURATION:10\n",
+            "#EXTINF:10,\n",
+            "seg0.aac\n",
+            "#EXTINF:10,\n",
+            "seg1.aac\n",
+            "#EXTINF:10,\n",
+            "seg2.aac\n",
+            "#EXT-X-ENDLIST\n"
+        );
+        // Le segment 1 n'est plus disponible sur le premier serveur
+        let primary = serve(vec![
+            response("200 OK", "", playlist),
+            response("200 OK", "", "a0"),
+            response("404 Not Found", "", ""),
+        ]);
+        let backup = serve(vec![
+            response("200 OK", "", playlist),
+            response("200 OK", "", "b1"),
+            response("200 OK", "", "b2"),
+        ]);
+        let master = format!(
+            "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=96000,CODECS=\"mp4a.40.2\"\n{primary}media.m3u8\n#EXT-X-STREAM-INF:BANDWIDTH=96000,CODECS=\"mp4a.40.2\"\n{backup}media.m3u8\n"
+        );
+        let url = serve(vec![response("200 OK", "", master)]);
+        let (rx, _) = start(&url).unwrap();
+        let segments = rx.iter().map(Result::unwrap).collect::<Vec<_>>();
+        let segments = segments.iter().map(|segment| (segment.sequence, &segment.data[..])).collect::<Vec<_>>();
+        assert_eq!(segments, [(0, &b"a0"[..]), (1, b"b1"), (2, b"b2")]);
+    }
+
+    #[test]
+    fn disk_cache() {
+        let playlist = |token: usize| {
+            format!(
+                "#EXTM3U\n#EXT-X-TARGETDURATION:10\n#EXT-X-KEY:METHOD=AES-128,URI=\"key\"\n#EXTINF:10,\nseg0.aac?token={token}\n#EXTINF:10,\nseg1.aac?token={token}\n#EXT-X-ENDLIST\n"
+            )
+        };
+        let cipher = libaes::Cipher::new_128(b"4567890123456789");
+        let encrypt = |data: &[u8], number: u128| cipher.cbc_encrypt(&number.to_be_bytes(), data);
+        // Les segments et la clé ne sont servis qu'une fois: la réécoute, sous un autre jeton, obtient les
+        // segments décryptés du cache sans la clé
+        let url = serve(vec![
+            response("200 OK", "", MASTER),
+            response("200 OK", "", playlist(1)),
+            response("200 OK", "", "4567890123456789"),
+            response("200 OK", "", encrypt(b"s0", 0)),
+            response("200 OK", "", encrypt(b"s1", 1)),
+            response("200 OK", "", MASTER),
+            response("200 OK", "", playlist(2)),
+        ]);
+        let dir = std::env::temp_dir().join(format!("hls_disk_cache_{}", std::process::id()));
+        let config = HlsConfig::builder().max_retries(0).cache_dir(&dir).build().unwrap();
+        for _ in 0..2 {
+            let (rx, _) = start_with(&format!("{url}master.m3u8"), config.clone()).unwrap();
+            let segments = rx.iter().map(Result::unwrap).collect::<Vec<_>>();
+            let data = segments.iter().map(|segment| &segment.data[..]).collect::<Vec<_>>();
+            assert_eq!(data, [b"s0", b"s1"]);
+        }
+        // La clé n'est pas écrite sur disque
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+        // Un autre serveur sert un autre contenu aux mêmes chemins
+        let other = serve(vec![
+            response("200 OK", "", MASTER),
+            response("200 OK", "", playlist(1)),
+            response("200 OK", "", "4567890123456789"),
+            response("200 OK", "", encrypt(b"t0", 0)),
+            response("200 OK", "", encrypt(b"t1", 1)),
+        ]);
+        let (rx, _) = start_with(&format!("{other}master.m3u8"), config).unwrap();
+        let segments = rx.iter().map(Result::unwrap).collect::<Vec<_>>();
+        let data = segments.iter().map(|segment| &segment.data[..]).collect::<Vec<_>>();
+        assert_eq!(data, [b"t0", b"t1"]);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 4);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn stream_error() {
+        // Connexion refusée: l'erreur est le dernier élément du flux
+        let config = HlsConfig::builder().max_retries(0).build().unwrap();
+        let rt = rt();
+        rt.block_on(async {
+            let mut stream = stream("http://127.0.0.1:9/master.m3u8", config.clone()).unwrap();
+            assert!(stream.next().await.unwrap().is_err());
+            assert!(stream.next().await.is_none());
+        });
+        assert!(stream("master.m3u8", config.clone()).is_err());
+        // Hors d'un runtime
+        assert!(stream("http://127.0.0.1:9/master.m3u8", config.clone()).is_err());
+
+        let (rx, handle) = start_with("http://127.0.0.1:9/master.m3u8", config).unwrap();
+        assert!(rx.recv().unwrap().is_err());
+        assert!(rx.recv().is_err());
+        assert!(handle.join().is_ok());
+    }
+
+    #[test]
+    fn cancel() {
+        // Un serveur qui ne répond jamais
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/master.m3u8", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let _connections = listener.incoming().collect::<Vec<_>>();
+        });
+
+        let (rx, handle) = start(&url).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let now = Instant::now();
+        handle.cancel();
+        assert!(handle.join().is_ok());
+        assert!(now.elapsed() < Duration::from_secs(5));
+        let e = rx.recv().unwrap().unwrap_err();
+        assert!(matches!(e.downcast_ref::<HlsError>(), Some(HlsError::Cancelled)));
+        assert!(rx.recv().is_err());
+    }
+
+    #[test]
+    fn cancel_pending() {
+        // Le second segment n'est jamais obtenu: le premier attend dans le canal
+        let playlist = "#EXTM3U\n#EXT-X-TARGETDURATION:1\n#EXTINF:1,\nseg1.aac\n#EXTINF:1,\nseg2.aac\n#EXT-X-ENDLIST\n";
+        let url = serve(vec![
+            response("200 OK", "", MASTER),
+            response("200 OK", "", playlist),
+            response("200 OK", "", "s1"),
+        ]);
+        let config = HlsConfig::builder().retry_delay(Duration::from_secs(1)).build().unwrap();
+        let (rx, handle) = start_with(&format!("{url}master.m3u8"), config).unwrap();
+        thread::sleep(Duration::from_millis(500));
+        handle.cancel();
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(rx.recv().unwrap().unwrap().sequence, 0);
+        let e = rx.recv().unwrap().unwrap_err();
+        assert!(matches!(e.downcast_ref::<HlsError>(), Some(HlsError::Cancelled)));
+        assert!(rx.recv().is_err());
+        assert!(handle.join().is_ok());
+    }
+
+    const MASTER: &str = "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=96000,CODECS=\"mp4a.40.2\"\nmedia.m3u8\n";
+
+    fn rt() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap()
+    }
+
+    // Un serveur HTTP qui sert les réponses dans l'ordre, une par connexion
+    fn serve(responses: Vec<Vec<u8>>) -> String {
+        use std::io::{Read, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for (response, connection) in responses.into_iter().zip(listener.incoming()) {
+                let mut connection = connection.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match connection.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                connection.write_all(&response).unwrap_or_default();
+            }
+        });
+        url
+    }
+
+    fn response(status: &str, headers: &str, body: impl AsRef<[u8]>) -> Vec<u8> {
+        let body = body.as_ref();
+        let mut response = format!(
+            "HTTP/1.1 {status}\r\nConnection: close\r\nContent-Length: {}\r\n{headers}\r\n",
+            body.len()
+        )
+        .into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    #[test]
+    fn http_status() {
+        let config = HlsConfig::builder().max_retries(2).retry_delay(Duration::from_millis(1)).build().unwrap();
+        let rt = rt();
+        let ok = response("200 OK", "", "segment");
+
+        // 4xx: pas de reprise
+        let url = serve(vec![response("404 Not Found", "", "<html>"), ok.clone()]);
+        let e = rt.block_on(get(&url, &config)).unwrap_err();
+        assert!(matches!(e.downcast_ref::<HlsError>(), Some(HlsError::HttpStatus(StatusCode::NOT_FOUND))));
+
+        // 5xx et 429: reprise, avec Retry-After
+        let url = serve(vec![
+            response("503 Service Unavailable", "", ""),
+            response("429 Too Many Requests", "Retry-After: 0\r\n", ""),
+            ok.clone(),
+        ]);
+        assert_eq!(rt.block_on(get(&url, &config)).unwrap(), &b"segment"[..]);
+
+        // Contenu tronqué
+        let truncated = "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 100\r\n\r\nseg"
+            .as_bytes()
+            .to_vec();
+        let url = serve(vec![truncated, ok]);
+        assert_eq!(rt.block_on(get(&url, &config)).unwrap(), &b"segment"[..]);
+
+        // Trop de tentatives
+        let url = serve(vec![response("500 Internal Server Error", "", ""); 3]);
+        let e = rt.block_on(get(&url, &config)).unwrap_err();
+        assert!(format!("{e:#}").contains("3 tentatives"));
+        assert!(e.downcast_ref::<HlsError>().is_some_and(HlsError::is_retryable));
+
+        assert!(backoff(Duration::from_millis(250), 2) >= Duration::from_secs(1));
+        assert!(backoff(Duration::from_millis(250), 2) <= Duration::from_millis(1500));
+        assert!(backoff(Duration::from_millis(250), 100) <= MAX_BACKOFF * 3 / 2);
+    }
+
+    #[test]
+    fn live_gap() {
+        let playlist = |sequence: usize| {
+            let segments = (sequence..sequence + 2).map(|n| format!("#EXTINF:1,\nseg{n}.aac\n")).collect::<String>();
+            format!("#EXTM3U\n#EXT-X-TARGETDURATION:1\n#EXT-X-MEDIA-SEQUENCE:{sequence}\n{segments}")
+        };
+        // Les segments 2 à 4 sont sortis de la playlist entre deux rechargements
+        let url = serve(vec![
+            response("200 OK", "", MASTER),
+            response("200 OK", "", playlist(0)),
+            response("200 OK", "", "s0"),
+            response("200 OK", "", "s1"),
+            response("200 OK", "", playlist(5)),
+            response("200 OK", "", "s5"),
+        ]);
+        let stats = HlsStats::default();
+        let config = HlsConfig::builder()
+            .live_start(LiveStart::Oldest)
+            .max_latency(None)
+            .stats(stats.clone())
+            .build()
+            .unwrap();
+        let (rx, _) = start_with(&format!("{url}master.m3u8"), config).unwrap();
+        let segments = rx.iter().take(3).map(Result::unwrap).collect::<Vec<_>>();
+        let segments = segments
+            .iter()
+            .map(|segment| (segment.sequence, segment.discontinuity))
+            .collect::<Vec<_>>();
+        assert_eq!(segments, [(0, false), (1, false), (5, true)]);
+        assert_eq!(stats.snapshot().missing, 3);
+    }
+
+    #[test]
+    fn etag() {
+        let playlist = "#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXTINF:4,\nseg1.aac\n";
+        let url = serve(vec![
+            response("200 OK", "ETag: \"v1\"\r\n", playlist),
+            response("304 Not Modified", "", ""),
+        ]);
+        let url = Url::parse(&url).unwrap();
+        let config = HlsConfig::default();
+        let rt = rt();
+        let mut cache = PlaylistCache::default();
+
+        rt.block_on(reload_media_playlist(&url, &mut cache, &config)).unwrap();
+        assert_eq!(cache.etag.as_deref(), Some("\"v1\""));
+        // La playlist précédente sert encore
+        let (media, _) = rt.block_on(reload_media_playlist(&url, &mut cache, &config)).unwrap();
+        assert_eq!(media.segments.num_elements(), 1);
+    }
+
+    #[test]
+    fn fmp4() {
+        let init = response("200 OK", "", "\x00\x00\x00\x08ftyp");
+        let url = serve(vec![init.clone(), init]);
+        let playlist = concat!(
+            "#EXTM3U\n",
+            "#EXT-X-TARGETDURATION:10\n",
+            "#EXT-X-MAP:URI=\"init.mp4\"\n",
+            "#EXTINF:10,\n",
+            "seg1.m4s\n",
+            "#EXTINF:10,\n",
+            "seg2.m4s\n",
+            "#EXT-X-MAP:URI=\"init2.mp4\"\n",
+            "#EXTINF:10,\n",
+            "seg3.m4s\n",
+            "#EXT-X-DISCONTINUITY\n",
+            "#EXTINF:10,\n",
+            "seg4.m4s\n",
+            "#EXT-X-ENDLIST\n"
+        );
+        let media = MediaPlaylist::from_str(playlist).unwrap();
+        let segments = media.segments.values().collect::<Vec<_>>();
+        assert_eq!(segment_map(&media, 1).unwrap().uri(), "init.mp4");
+        assert_eq!(segment_map(&media, 2).unwrap().uri(), "init2.mp4");
+
+        // La section d'initialisation précède le premier segment, puis chaque changement
+        let config = HlsConfig::default();
+        let media_url = Url::parse(&url).unwrap();
+        let mut session = Session::default();
+        let rt = rt();
+        let mut stream = Vec::new();
+        for (number, media_segment) in segments.iter().enumerate() {
+            let map = segment_map(&media, number);
+            let data = Bytes::from_static(b"moof");
+            stream.push(
+                rt.block_on(process_segment(&media_url, media_segment, map, data, false, &config, &mut session))
+                    .unwrap(),
+            );
+        }
+        let data = stream.iter().map(|segment| &segment.data[..]).collect::<Vec<_>>();
+        let init = &b"\x00\x00\x00\x08ftypmoof"[..];
+        assert_eq!(data, [init, b"moof", init, init]);
+        // Le décodeur doit être réinitialisé au changement de section, et à une discontinuité sous la même section,
+        // qui est alors transmise de nouveau sans être obtenue
+        let discontinuities = stream.iter().map(|segment| segment.discontinuity).collect::<Vec<_>>();
+        assert_eq!(discontinuities, [false, false, true, true]);
+
+        // Une discontinuité que la playlist n'annonce pas, comme le redémarrage d'un flux «live»
+        let map = segment_map(&media, 2);
+        let data = Bytes::from_static(b"moof");
+        let segment = rt
+            .block_on(process_segment(&media_url, segments[2], map, data, true, &config, &mut session))
+            .unwrap();
+        assert_eq!(&segment.data[..], init);
+        assert!(segment.discontinuity);
+    }
+
+    #[test]
+    fn encrypted() {
+        let config = HlsConfig::builder().max_retries(0).build().unwrap();
+        let rt = rt();
+        let iv = 3u128.to_be_bytes();
+        let key = SegmentKey::new(b"4567890123456789", &iv).unwrap();
+        let plain = (0..5000).map(|b| b as u8).collect::<Vec<_>>();
+        let encrypted = libaes::Cipher::new_128(b"4567890123456789").cbc_encrypt(&iv, &plain);
+        let mut file = b"autre".to_vec();
+        file.extend_from_slice(&encrypted);
+
+        let url = serve(vec![
+            response("200 OK", "", &encrypted),
+            // Le serveur ignore la plage
+            response("200 OK", "", &file),
+            response("200 OK", "", &encrypted[..encrypted.len() - 1]),
+        ]);
+        assert_eq!(rt.block_on(get_segment(&url, None, Some(&key), &config)).unwrap(), plain);
+        let range = 5..5 + encrypted.len();
+        assert_eq!(rt.block_on(get_segment(&url, Some(range), Some(&key), &config)).unwrap(), plain);
+        let e = rt.block_on(get_segment(&url, None, Some(&key), &config)).unwrap_err();
+        assert!(matches!(e.downcast_ref::<HlsError>(), Some(HlsError::Decrypt(_))));
+    }
+
+    #[test]
+    fn zero_copy() {
+        let config = HlsConfig::default();
+        let media_url = Url::parse("https://cdn/media.m3u8").unwrap();
+        let media = MediaPlaylist::from_str("#EXTM3U\n#EXT-X-TARGETDURATION:10\n#EXTINF:10,\nseg1.aac\n#EXT-X-ENDLIST\n").unwrap();
+        let media_segment = media.segments.values().next().unwrap();
+        let rt = rt();
+
+        // Le segment transmis est le tampon reçu, sans les octets qui précèdent la première trame
+        let silence = adts::silence(
+            AdtsConfig::parse(&[0xFF, 0xF1, 0x50, 0x80, 0x2E, 0x7F, 0xFC]).unwrap(),
+            Duration::from_secs(1),
+        )
+        .unwrap();
+        let data = Bytes::from([b"ID3".as_slice(), &silence].concat());
+        let segment = rt
+            .block_on(process_segment(
+                &media_url,
+                media_segment,
+                None,
+                data.clone(),
+                false,
+                &config,
+                &mut Session::default(),
+            ))
+            .unwrap();
+        assert_eq!(segment.data, silence);
+        assert_eq!(segment.data.as_ptr(), data[3..].as_ptr());
+    }
+
+    #[test]
+    fn ranges() {
+        let config = HlsConfig::default();
+        let rt = rt();
+        let url = serve(vec![
+            response("206 Partial Content", "", "seg"),
+            response("200 OK", "", "0123456789"),
+            response("200 OK", "", "0123456789"),
+        ]);
+        assert_eq!(rt.block_on(get_range(&url, Some(0..3), &config)).unwrap(), &b"seg"[..]);
+
+        // Le serveur ignore la plage
+        assert_eq!(rt.block_on(get_range(&url, Some(2..5), &config)).unwrap(), &b"234"[..]);
+        let e = rt.block_on(get_range(&url, Some(8..12), &config)).unwrap_err();
+        assert!(matches!(e.downcast_ref::<HlsError>(), Some(HlsError::Playlist(_))));
+
+        let playlist = concat!(
+            "#EXTM3U\n",
+            "#EXT-X-TARGETDURATION:10\n",
+            "#EXTINF:10,\n",
+            "#EXT-X-BYTERANGE:1000@0\n",
+            "audio.aac\n",
+            "#EXTINF:10,\n",
+            "#EXT-X-BYTERANGE:500\n",
+            "audio.aac\n",
+            "#EXT-X-ENDLIST\n"
+        );
+        let media = MediaPlaylist::from_str(playlist).unwrap();
+        let ranges = media
+            .segments
+            .values()
+            .map(|s| byte_range(s.byte_range.map(|range| *range)))
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, [Some(0..1000), Some(1000..1500)]);
+    }
+
+    #[test]
+    fn gap() {
+        // Une trame AAC-LC stéréo silencieuse de 16 octets
+        let frame = [
+            0xFF, 0xF1, 0x50, 0x80, 0x02, 0x1F, 0xFC, 0x21, 0x00, 0x49, 0x90, 0x02, 0x19, 0x00, 0x23, 0x80,
+        ];
+        let playlist = concat!(
+            "#EXTM3U\n",
+            "#EXT-X-TARGETDURATION:1\n",
+            "#EXTINF:1,\n",
+            "seg0.aac\n",
+            "#EXT-X-GAP\n",
+            "#EXTINF:1,\n",
+            "seg1.aac\n",
+            "#EXTINF:1,\n",
+            "seg2.aac\n",
+            "#EXT-X-ENDLIST\n"
+        );
+
+        for (policy, silence) in [(GapPolicy::Skip, 0), (GapPolicy::Silence, 43 * 16)] {
+            let url = serve(vec![
+                response("200 OK", "", MASTER),
+                response("200 OK", "", playlist),
+                response("200 OK", "", frame),
+                response("200 OK", "", frame),
+            ]);
+            let config = HlsConfig::builder().gap(policy).build().unwrap();
+            let (rx, _) = start_with(&url, config).unwrap();
+            let lengths = rx.iter().map(|message| message.unwrap().data.len()).collect::<Vec<_>>();
+            match silence {
+                0 => assert_eq!(lengths, [16, 16]),
+                silence => assert_eq!(lengths, [16, silence, 16]),
+            }
+        }
+    }
+
+    #[test]
+    fn discontinuity() {
+        let playlist = concat!(
+            "#EXTM3U\n",
+            "#EXT-X-TARGETDURATION:10\n",
+            "#EXT-X-MEDIA-SEQUENCE:5\n",
+            "#EXT-X-PROGRAM-DATE-TIME:2024-01-01T00:00:00Z\n",
+            "#EXTINF:10,\n",
+            "seg5.aac\n",
+            "#EXT-X-DISCONTINUITY\n",
+            "#EXTINF:8.5,\n",
+            "seg6.aac\n",
+            "#EXT-X-ENDLIST\n"
+        );
+        let url = serve(vec![
+            response("200 OK", "", MASTER),
+            response("200 OK", "", playlist),
+            response("200 OK", "", "pub"),
+            response("200 OK", "", "emission"),
+        ]);
+        let (rx, _) = start(&url).unwrap();
+        let segments = rx.iter().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(&segments[0].data[..], b"pub");
+        assert_eq!((segments[0].sequence, segments[0].discontinuity), (5, false));
+        assert_eq!(segments[0].program_date_time.as_deref(), Some("2024-01-01T00:00:00Z"));
+        assert_eq!((segments[1].sequence, segments[1].discontinuity), (6, true));
+        assert_eq!(segments[1].duration, Duration::from_secs_f64(8.5));
+    }
 }
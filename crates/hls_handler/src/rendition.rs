@@ -0,0 +1,114 @@
+use hls_m3u8::tags::ExtXMedia;
+use hls_m3u8::types::MediaType;
+
+// Une piste audio d'un groupe EXT-X-MEDIA: une langue, l'audiodescription, ...
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Rendition {
+    pub group_id: String,
+    pub name: String,
+    pub language: Option<String>,
+    pub default: bool,
+    pub autoselect: bool,
+    pub characteristics: Option<String>, // public.accessibility.describes-video pour l'audiodescription
+    pub uri: Option<String>,             // Sans URI, l'audio est celui de la variante
+}
+
+// Sélection de la piste audio parmi celles du groupe de la variante
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum RenditionPolicy {
+    // DEFAULT=YES, sinon la première du groupe
+    #[default]
+    Default,
+    // Une langue RFC 5646: «fr» choisit aussi «fr-CA»
+    Language(String),
+    Name(String),
+}
+
+// Les pistes audio de la MasterPlaylist
+pub(crate) fn renditions(media: &[ExtXMedia]) -> Vec<Rendition> {
+    media
+        .iter()
+        .filter(|media| media.media_type == MediaType::Audio)
+        .map(|media| Rendition {
+            group_id: media.group_id().to_string(),
+            name: media.name().to_string(),
+            language: media.language().map(|language| language.to_string()),
+            default: media.is_default,
+            autoselect: media.is_autoselect,
+            characteristics: media.characteristics().map(|characteristics| characteristics.to_string()),
+            uri: media.uri().map(|uri| uri.to_string()),
+        })
+        .collect()
+}
+
+// La piste du groupe selon la politique. Faute de correspondance, celle par défaut
+pub(crate) fn select<'a>(renditions: &'a [Rendition], group_id: &str, policy: &RenditionPolicy) -> Option<&'a Rendition> {
+    let group = renditions.iter().filter(|rendition| rendition.group_id == group_id).collect::<Vec<_>>();
+    let language = |rendition: &&Rendition, exact: bool| match (&rendition.language, policy) {
+        (Some(language), RenditionPolicy::Language(wanted)) => match exact {
+            true => language.eq_ignore_ascii_case(wanted),
+            false => language.split('-').next().is_some_and(|primary| primary.eq_ignore_ascii_case(wanted)),
+        },
+        _ => false,
+    };
+    let chosen = match policy {
+        RenditionPolicy::Default => None,
+        RenditionPolicy::Language(_) => group
+            .iter()
+            .find(|rendition| language(rendition, true))
+            .or_else(|| group.iter().find(|rendition| language(rendition, false))),
+        RenditionPolicy::Name(name) => group.iter().find(|rendition| rendition.name == *name),
+    };
+    chosen
+        .or_else(|| group.iter().find(|rendition| rendition.default))
+        .or(group.first())
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hls_m3u8::MasterPlaylist;
+
+    const MASTER: &str = concat!(
+        "#EXTM3U\n",
+        "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aac\",NAME=\"Français\",LANGUAGE=\"fr-CA\",DEFAULT=YES,AUTOSELECT=YES,URI=\"fr.m3u8\"\n",
+        "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aac\",NAME=\"English\",LANGUAGE=\"en\",AUTOSELECT=YES,URI=\"en.m3u8\"\n",
+        "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"aac\",NAME=\"Audiodescription\",LANGUAGE=\"fr\",CHARACTERISTICS=\"public.accessibility.describes-video\",URI=\"dv.m3u8\"\n",
+        "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"he\",NAME=\"Français\",LANGUAGE=\"fr\",DEFAULT=YES\n",
+        "#EXT-X-STREAM-INF:BANDWIDTH=96000,CODECS=\"mp4a.40.2\",AUDIO=\"aac\"\n",
+        "lc_96.m3u8\n",
+    );
+
+    fn pick(group_id: &str, policy: RenditionPolicy) -> Option<String> {
+        let master = MasterPlaylist::try_from(MASTER).unwrap();
+        let renditions = renditions(&master.media);
+        select(&renditions, group_id, &policy).map(|rendition| rendition.name.clone())
+    }
+
+    #[test]
+    fn policy() {
+        let master = MasterPlaylist::try_from(MASTER).unwrap();
+        let renditions = renditions(&master.media);
+        assert_eq!(renditions.len(), 4);
+        assert_eq!(renditions[0].language.as_deref(), Some("fr-CA"));
+        assert_eq!(renditions[0].uri.as_deref(), Some("fr.m3u8"));
+        assert!(renditions[2].characteristics.as_deref().is_some_and(|c| c.contains("describes-video")));
+        assert_eq!(renditions[3].uri, None);
+
+        assert_eq!(pick("aac", RenditionPolicy::Default).as_deref(), Some("Français"));
+        assert_eq!(pick("aac", RenditionPolicy::Language("EN".to_owned())).as_deref(), Some("English"));
+        // La langue exacte, puis la langue principale
+        assert_eq!(
+            pick("aac", RenditionPolicy::Language("fr".to_owned())).as_deref(),
+            Some("Audiodescription")
+        );
+        assert_eq!(pick("aac", RenditionPolicy::Language("fr-CA".to_owned())).as_deref(), Some("Français"));
+        assert_eq!(
+            pick("aac", RenditionPolicy::Name("Audiodescription".to_owned())).as_deref(),
+            Some("Audiodescription")
+        );
+        assert_eq!(pick("aac", RenditionPolicy::Language("de".to_owned())).as_deref(), Some("Français"));
+        assert_eq!(pick("aucun", RenditionPolicy::Default), None);
+    }
+}
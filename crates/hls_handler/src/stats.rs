@@ -0,0 +1,108 @@
+use crate::is_live;
+use crate::rendition::Rendition;
+use hls_m3u8::MediaPlaylist;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// La progression et la santé du flux au dernier segment transmis
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub live: bool,
+    pub total_duration: Duration, // Durée de la playlist, la fenêtre courante pour un flux «live»
+    pub segment: usize,           // Index du dernier segment transmis dans la playlist
+    pub segments: usize,
+    pub position: Duration,              // Durée transmise depuis le début
+    pub bytes: u64,                      // Octets téléchargés, playlists et clés comprises
+    pub download_time: Option<Duration>, // Du dernier segment
+    pub retries: usize,
+    pub missing: usize,              // Segments «live» sortis de la playlist avant d'être obtenus
+    pub bandwidth: u64,              // De la variante choisie
+    pub live_edge: Option<Duration>, // Durée des segments qui suivent le dernier transmis
+    pub renditions: Vec<Rendition>,  // Les pistes audio du groupe de la variante
+    pub rendition: Option<Rendition>,
+}
+
+impl Stats {
+    // Fraction transmise d'un flux sur demande
+    pub fn progress(&self) -> Option<f64> {
+        match (self.live, self.total_duration.is_zero()) {
+            (false, false) => Some((self.position.as_secs_f64() / self.total_duration.as_secs_f64()).min(1.0)),
+            _ => None,
+        }
+    }
+}
+
+// Un instantané partagé entre le pipeline et son consommateur
+#[derive(Clone, Debug, Default)]
+pub struct HlsStats(Arc<Mutex<Stats>>);
+
+impl HlsStats {
+    pub fn snapshot(&self) -> Stats {
+        self.0.lock().expect("Poisoned lock").clone()
+    }
+
+    pub(crate) fn update(&self, update: impl FnOnce(&mut Stats)) {
+        update(&mut self.0.lock().expect("Poisoned lock"));
+    }
+
+    // Le segment a été transmis
+    pub(crate) fn segment(&self, media: &MediaPlaylist, number: usize, duration: Duration, bandwidth: u64) {
+        let durations = media
+            .segments
+            .values()
+            .map(|media_segment| (media_segment.number(), media_segment.duration.duration()))
+            .collect::<Vec<_>>();
+        let index = durations.iter().position(|(n, _)| *n == number);
+        let total = durations.iter().map(|(_, duration)| *duration).sum::<Duration>();
+
+        self.update(|stats| {
+            stats.live = is_live(media);
+            stats.total_duration = total;
+            stats.segments = durations.len();
+            stats.position += duration;
+            stats.bandwidth = bandwidth;
+            if let Some(index) = index {
+                stats.segment = index;
+                stats.live_edge = stats.live.then(|| durations[index + 1..].iter().map(|(_, duration)| *duration).sum());
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn segment() {
+        let playlist = concat!(
+            "#EXTM3U\n",
+            "#EXT-X-TARGETDURATION:10\n",
+            "#EXT-X-MEDIA-SEQUENCE:7\n",
+            "#EXTINF:10,\n",
+            "seg7.aac\n",
+            "#EXTINF:10,\n",
+            "seg8.aac\n",
+            "#EXTINF:5,\n",
+            "seg9.aac\n",
+        );
+        let mut media = MediaPlaylist::from_str(playlist).unwrap();
+        let stats = HlsStats::default();
+        stats.segment(&media, 8, Duration::from_secs(10), 96000);
+        let snapshot = stats.snapshot();
+        assert!(snapshot.live);
+        assert_eq!((snapshot.segment, snapshot.segments), (1, 3));
+        assert_eq!(snapshot.total_duration, Duration::from_secs(25));
+        assert_eq!(snapshot.live_edge, Some(Duration::from_secs(5)));
+        assert_eq!(snapshot.progress(), None);
+
+        media.has_end_list = true;
+        let stats = HlsStats::default();
+        stats.segment(&media, 7, Duration::from_secs(10), 96000);
+        stats.segment(&media, 8, Duration::from_secs(10), 96000);
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.live_edge, None);
+        assert_eq!(snapshot.progress(), Some(0.8));
+    }
+}
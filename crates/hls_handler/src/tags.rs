@@ -0,0 +1,220 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Range;
+use std::time::Duration;
+
+// Un segment partiel LL-HLS (EXT-X-PART)
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Part {
+    pub(crate) uri: String,
+    pub(crate) duration: Duration,
+    pub(crate) range: Option<Range<usize>>,
+    pub(crate) gap: bool,
+    pub(crate) discontinuity: bool, // Pour la première partie d'un segment EXT-X-DISCONTINUITY
+}
+
+// Les balises inconnues de hls_m3u8 sont reléguées dans `unknown`, sans leur position dans la playlist.
+// Celles qui qualifient un segment sont relevées directement dans le texte
+#[derive(Debug, Default)]
+pub(crate) struct Tags {
+    pub(crate) gaps: HashSet<usize>,        // Numéros des segments EXT-X-GAP
+    pub(crate) hold_back: Option<Duration>, // HOLD-BACK de EXT-X-SERVER-CONTROL
+    pub(crate) part_hold_back: Option<Duration>,
+    pub(crate) can_block_reload: bool,
+    pub(crate) can_skip: bool,                                       // CAN-SKIP-UNTIL: le serveur accepte _HLS_skip=YES
+    pub(crate) part_target: Option<Duration>,                        // EXT-X-PART-INF: la playlist a des segments partiels
+    pub(crate) parts: BTreeMap<usize, Vec<Part>>,                    // Selon le numéro du segment, y compris celui en cours
+    pub(crate) preload_hint: Option<(String, Option<Range<usize>>)>, // La prochaine partie (EXT-X-PRELOAD-HINT)
+}
+
+impl Tags {
+    pub(crate) fn parse(playlist: &str, media_sequence: usize) -> Self {
+        let mut tags = Tags::default();
+        let mut number = media_sequence;
+        let mut gap = false;
+        let mut discontinuity = false;
+        let mut ends: HashMap<String, usize> = HashMap::new(); // Fin de la dernière plage de chaque URI
+
+        for line in playlist.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            if line == "#EXT-X-GAP" {
+                gap = true;
+            } else if line == "#EXT-X-DISCONTINUITY" {
+                discontinuity = true;
+            } else if let Some(attributes) = line.strip_prefix("#EXT-X-SERVER-CONTROL:") {
+                tags.hold_back = seconds(attribute(attributes, "HOLD-BACK"));
+                tags.part_hold_back = seconds(attribute(attributes, "PART-HOLD-BACK"));
+                tags.can_block_reload = attribute(attributes, "CAN-BLOCK-RELOAD") == Some("YES");
+                tags.can_skip = attribute(attributes, "CAN-SKIP-UNTIL").is_some();
+            } else if let Some(attributes) = line.strip_prefix("#EXT-X-PART-INF:") {
+                tags.part_target = seconds(attribute(attributes, "PART-TARGET"));
+            } else if let Some(attributes) = line.strip_prefix("#EXT-X-PART:") {
+                let (Some(uri), Some(duration)) = (attribute(attributes, "URI"), seconds(attribute(attributes, "DURATION"))) else {
+                    continue;
+                };
+                let parts = tags.parts.entry(number).or_default();
+                parts.push(Part {
+                    uri: uri.to_owned(),
+                    duration,
+                    range: attribute(attributes, "BYTERANGE").and_then(|range| byte_range(range, uri, &mut ends)),
+                    gap: attribute(attributes, "GAP") == Some("YES"),
+                    discontinuity: discontinuity && parts.is_empty(),
+                });
+            } else if let Some(attributes) = line.strip_prefix("#EXT-X-PRELOAD-HINT:") {
+                if attribute(attributes, "TYPE") == Some("PART")
+                    && let Some(uri) = attribute(attributes, "URI")
+                {
+                    // Une plage sans longueur court jusqu'à la fin d'une ressource encore incomplète: l'indice est ignoré
+                    let start = attribute(attributes, "BYTERANGE-START").and_then(|start| start.parse::<usize>().ok());
+                    let len = attribute(attributes, "BYTERANGE-LENGTH").and_then(|len| len.parse::<usize>().ok());
+                    tags.preload_hint = match (start, len) {
+                        (None, _) => Some((uri.to_owned(), None)),
+                        (Some(start), Some(len)) => Some((uri.to_owned(), Some(start..start + len))),
+                        (Some(_), None) => None,
+                    };
+                }
+            } else if !line.starts_with('#') {
+                // L'URI termine le segment
+                if gap {
+                    tags.gaps.insert(number);
+                }
+                number += 1;
+                gap = false;
+                discontinuity = false;
+            }
+        }
+        tags
+    }
+}
+
+// Une mise à jour partielle (EXT-X-SKIP) omet les segments les plus anciens. Le numéro de séquence
+// est avancé d'autant pour que hls_m3u8 numérote correctement ceux qui restent
+pub(crate) fn expand_skip(playlist: &str) -> Cow<'_, str> {
+    let skipped = playlist
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("#EXT-X-SKIP:"))
+        .and_then(|attributes| attribute(attributes, "SKIPPED-SEGMENTS"))
+        .and_then(|skipped| skipped.parse::<usize>().ok());
+    let Some(skipped) = skipped else {
+        return Cow::Borrowed(playlist);
+    };
+
+    let mut expanded = String::with_capacity(playlist.len());
+    for line in playlist.lines() {
+        if line.trim().starts_with("#EXT-X-SKIP:") {
+            continue;
+        }
+        match line
+            .trim()
+            .strip_prefix("#EXT-X-MEDIA-SEQUENCE:")
+            .and_then(|sequence| sequence.parse::<usize>().ok())
+        {
+            Some(sequence) => expanded.push_str(&format!("#EXT-X-MEDIA-SEQUENCE:{}", sequence + skipped)),
+            None => expanded.push_str(line),
+        }
+        expanded.push('\n');
+    }
+    Cow::Owned(expanded)
+}
+
+fn seconds(value: Option<&str>) -> Option<Duration> {
+    value
+        .and_then(|value| value.parse::<f64>().ok())
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+}
+
+// BYTERANGE="n[@o]": sans décalage, la plage suit la précédente de la même URI
+fn byte_range(range: &str, uri: &str, ends: &mut HashMap<String, usize>) -> Option<Range<usize>> {
+    let (len, start) = match range.split_once('@') {
+        Some((len, start)) => (len.parse::<usize>().ok()?, start.parse::<usize>().ok()?),
+        None => (range.parse::<usize>().ok()?, ends.get(uri).copied().unwrap_or(0)),
+    };
+    ends.insert(uri.to_owned(), start + len);
+    Some(start..start + len)
+}
+
+// La valeur d'un attribut d'une liste NOM=valeur, sans ses guillemets. Une virgule entre guillemets
+// ne sépare pas les attributs
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+    let mut quoted = false;
+    attributes
+        .split(|c| {
+            if c == '"' {
+                quoted = !quoted;
+            }
+            c == ',' && !quoted
+        })
+        .filter_map(|attribute| attribute.split_once('='))
+        .find(|(key, _)| key.trim() == name)
+        .map(|(_, value)| value.trim().trim_matches('"'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gaps() {
+        let playlist = concat!(
+            "#EXTM3U\n",
+            "#EXT-X-TARGETDURATION:10\n",
+            "#EXT-X-MEDIA-SEQUENCE:20\n",
+            "#EXTINF:10,\n",
+            "seg20.aac\n",
+            "#EXT-X-GAP\n",
+            "#EXTINF:10,\n",
+            "seg21.aac\n",
+            "#EXTINF:10,\n",
+            "\n",
+            "seg22.aac\n",
+            "#EXTINF:10,\r\n",
+            "#EXT-X-GAP\r\n",
+            "seg23.aac\r\n",
+        );
+        assert_eq!(Tags::parse(playlist, 20).gaps, HashSet::from([21, 23]));
+    }
+
+    #[test]
+    fn server_control() {
+        let playlist = "#EXTM3U\n#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,HOLD-BACK=12.5,PART-HOLD-BACK=3.0\n";
+        assert_eq!(Tags::parse(playlist, 0).hold_back, Some(Duration::from_secs_f64(12.5)));
+        assert_eq!(Tags::parse("#EXTM3U\n#EXT-X-SERVER-CONTROL:CAN-SKIP-UNTIL=36\n", 0).hold_back, None);
+    }
+
+    #[test]
+    fn low_latency() {
+        let playlist = concat!(
+            "#EXTM3U\n",
+            "#EXT-X-TARGETDURATION:4\n",
+            "#EXT-X-SERVER-CONTROL:CAN-BLOCK-RELOAD=YES,PART-HOLD-BACK=1.0,CAN-SKIP-UNTIL=24\n",
+            "#EXT-X-PART-INF:PART-TARGET=0.5\n",
+            "#EXT-X-MEDIA-SEQUENCE:10\n",
+            "#EXT-X-SKIP:SKIPPED-SEGMENTS=3\n",
+            "#EXT-X-PART:DURATION=0.5,URI=\"seg13.mp4\",BYTERANGE=\"100@0\",INDEPENDENT=YES\n",
+            "#EXT-X-PART:DURATION=0.5,URI=\"seg13.mp4\",BYTERANGE=\"50\"\n",
+            "#EXTINF:1,\n",
+            "seg13.mp4\n",
+            "#EXT-X-DISCONTINUITY\n",
+            "#EXT-X-PART:DURATION=0.5,URI=\"part14.0.mp4?a=1,2\"\n",
+            "#EXT-X-PART:DURATION=0.5,URI=\"part14.1.mp4\",GAP=YES\n",
+            "#EXT-X-PRELOAD-HINT:TYPE=PART,URI=\"part14.2.mp4\"\n",
+        );
+        let expanded = expand_skip(playlist);
+        assert!(expanded.contains("#EXT-X-MEDIA-SEQUENCE:13\n"));
+        assert!(!expanded.contains("#EXT-X-SKIP"));
+        assert!(matches!(expand_skip("#EXTM3U\n"), Cow::Borrowed(_)));
+
+        let tags = Tags::parse(&expanded, 13);
+        assert!(tags.can_block_reload && tags.can_skip);
+        assert_eq!(tags.part_hold_back, Some(Duration::from_secs(1)));
+        assert_eq!(tags.part_target, Some(Duration::from_millis(500)));
+        assert_eq!(
+            tags.parts[&13].iter().map(|part| part.range.clone()).collect::<Vec<_>>(),
+            [Some(0..100), Some(100..150)]
+        );
+        let parts = &tags.parts[&14];
+        assert_eq!(parts[0].uri, "part14.0.mp4?a=1,2");
+        assert_eq!((parts[0].discontinuity, parts[0].gap), (true, false));
+        assert_eq!((parts[1].discontinuity, parts[1].gap), (false, true));
+        assert_eq!(tags.preload_hint, Some(("part14.2.mp4".to_owned(), None)));
+    }
+}
@@ -0,0 +1,347 @@
+use crate::HlsError;
+use anyhow::{Result, bail};
+use bytes::Bytes;
+use std::collections::{HashMap, HashSet};
+
+// Démultiplexeur MPEG-TS (ISO/IEC 13818-1) limité à ce qu'il faut pour un segment HLS audio
+const PACKET_LEN: usize = 188;
+const SYNC: u8 = 0x47;
+const PAT_PID: u16 = 0;
+const NULL_PID: u16 = 0x1FFF;
+const STREAM_TYPE_ADTS: u8 = 0x0F; // AAC avec entêtes ADTS
+const STREAM_TYPE_ID3: u8 = 0x15; // Métadonnées minutées ID3
+
+// Un paquet PES réassemblé et son horodatage en unités de 90 kHz
+#[derive(Debug, PartialEq)]
+pub(crate) struct Pes {
+    pub(crate) pts: Option<u64>,
+    pub(crate) data: Bytes,
+}
+
+// Les PES AAC et ceux des métadonnées ID3 du même programme
+#[derive(Debug, Default)]
+pub(crate) struct Demuxed {
+    pub(crate) audio: Vec<Pes>,
+    pub(crate) metadata: Vec<Pes>,
+}
+
+struct Packet<'a> {
+    pid: u16,
+    start: bool, // payload_unit_start_indicator
+    continuity: u8,
+    payload: &'a [u8],
+}
+
+// Les paquets valides du segment. Les octets corrompus sont sautés jusqu'à la prochaine synchronisation
+fn packets(data: &[u8]) -> Vec<Packet<'_>> {
+    let mut packets = Vec::new();
+    let mut i = 0;
+    while i + PACKET_LEN <= data.len() {
+        // Un octet de synchronisation doit être suivi d'un autre, 188 octets plus loin
+        if data[i] != SYNC || data.get(i + PACKET_LEN).is_some_and(|&b| b != SYNC) {
+            i += 1;
+            continue;
+        }
+        let packet = &data[i..i + PACKET_LEN];
+        i += PACKET_LEN;
+
+        if packet[1] & 0x80 != 0 {
+            continue; // transport_error_indicator
+        }
+        let payload = match (packet[3] >> 4) & 0x03 {
+            0b01 => &packet[4..],
+            0b11 => match packet.get(5 + packet[4] as usize..) {
+                Some(payload) => payload,
+                None => continue,
+            },
+            _ => continue, // Pas de payload
+        };
+        packets.push(Packet {
+            pid: ((packet[1] & 0x1F) as u16) << 8 | packet[2] as u16,
+            start: packet[1] & 0x40 != 0,
+            continuity: packet[3] & 0x0F,
+            payload,
+        });
+    }
+    packets
+}
+
+// La section PSI qui débute dans le paquet, sans son CRC
+fn section<'a>(packet: &Packet<'a>, table_id: u8) -> Option<&'a [u8]> {
+    if !packet.start {
+        return None;
+    }
+    let pointer = *packet.payload.first()? as usize;
+    let section = packet.payload.get(1 + pointer..)?;
+    if *section.first()? != table_id {
+        return None;
+    }
+    let len = ((section.get(1)? & 0x0F) as usize) << 8 | *section.get(2)? as usize;
+    // Une section tronquée est lue jusqu'à la fin du paquet
+    section.get(..(3 + len).saturating_sub(4).min(section.len()))
+}
+
+// Les PID des PMT de tous les programmes
+fn parse_pat(section: &[u8], pmt_pids: &mut Vec<u16>) {
+    for entry in section.get(8..).unwrap_or_default().chunks_exact(4) {
+        let program = (entry[0] as u16) << 8 | entry[1] as u16;
+        let pid = ((entry[2] & 0x1F) as u16) << 8 | entry[3] as u16;
+        if program != 0 && !pmt_pids.contains(&pid) {
+            pmt_pids.push(pid);
+        }
+    }
+}
+
+// Les flux élémentaires du programme: (stream_type, PID)
+fn parse_pmt(section: &[u8]) -> Vec<(u8, u16)> {
+    let mut streams = Vec::new();
+    let Some(&[hi, lo]) = section.get(10..12) else {
+        return streams;
+    };
+    let mut i = 12 + (((hi & 0x0F) as usize) << 8 | lo as usize);
+    while let Some(&[stream_type, pid_hi, pid_lo, len_hi, len_lo]) = section.get(i..i + 5) {
+        streams.push((stream_type, ((pid_hi & 0x1F) as u16) << 8 | pid_lo as u16));
+        i += 5 + (((len_hi & 0x0F) as usize) << 8 | len_lo as usize);
+    }
+    streams
+}
+
+fn parse_pts(b: &[u8]) -> u64 {
+    ((b[0] as u64 >> 1) & 0x07) << 30 | (b[1] as u64) << 22 | (b[2] as u64 >> 1) << 15 | (b[3] as u64) << 7 | b[4] as u64 >> 1
+}
+
+// Retirer l'entête PES, sans copier le contenu. Un paquet sans préfixe de départ est ignoré
+fn parse_pes(data: Vec<u8>) -> Option<Pes> {
+    let [0x00, 0x00, 0x01, stream_id, len_hi, len_lo, ..] = *data else {
+        return None;
+    };
+    let len = (len_hi as usize) << 8 | len_lo as usize;
+    // Une longueur nulle est permise pour un flux non borné
+    let end = match len {
+        0 => data.len(),
+        len => (6 + len).min(data.len()),
+    };
+    let (pts, start) = match stream_id {
+        // Flux sans entête optionnel
+        0xBC | 0xBE | 0xBF | 0xF0 | 0xF1 | 0xF2 | 0xF8 | 0xFF => (None, 6),
+        _ => {
+            let flags = *data[..end].get(7)?;
+            let header_len = *data[..end].get(8)? as usize;
+            let pts = match flags & 0x80 {
+                0 => None,
+                _ => Some(parse_pts(data[..end].get(9..14)?)),
+            };
+            (pts, 9 + header_len)
+        }
+    };
+    if start > end {
+        return None;
+    }
+    Some(Pes {
+        pts,
+        data: Bytes::from(data).slice(start..end),
+    })
+}
+
+// Les PES du flux élémentaire, réassemblés à travers les paquets
+fn reassemble(packets: &[Packet], pid: u16) -> Vec<Pes> {
+    let mut pes = Vec::new();
+    let mut pending: Option<Vec<u8>> = None;
+    let mut continuity: Option<u8> = None;
+
+    for packet in packets.iter().filter(|packet| packet.pid == pid) {
+        // Un paquet répété est ignoré. Une rupture de continuité n'interrompt pas le PES:
+        // le décodeur se resynchronise sur l'entête ADTS suivant
+        if continuity == Some(packet.continuity) {
+            continue;
+        }
+        continuity = Some(packet.continuity);
+
+        if packet.start {
+            pes.extend(pending.take().and_then(parse_pes));
+            pending = Some(packet.payload.to_vec());
+        } else if let Some(pending) = &mut pending {
+            pending.extend_from_slice(packet.payload);
+        }
+    }
+    pes.extend(pending.and_then(parse_pes));
+    pes
+}
+
+// Les PES du premier programme qui contient du AAC. Les tables sont relevées dans tout le segment
+// avant l'extraction, peu importe l'ordre des paquets
+pub(crate) fn demux(data: &[u8]) -> Result<Demuxed> {
+    let packets = packets(data);
+    if packets.is_empty() {
+        bail!(HlsError::Demux("Pas de paquet TS".to_owned()));
+    }
+
+    let mut pmt_pids = Vec::new();
+    for packet in packets.iter().filter(|packet| packet.pid == PAT_PID) {
+        if let Some(section) = section(packet, 0x00) {
+            parse_pat(section, &mut pmt_pids);
+        }
+    }
+    if pmt_pids.is_empty() {
+        bail!(HlsError::Demux("Pas de PAT".to_owned()));
+    }
+
+    let mut programs: HashMap<u16, Vec<(u8, u16)>> = HashMap::new();
+    let pmt_set = pmt_pids.iter().copied().collect::<HashSet<_>>();
+    for packet in packets.iter().filter(|packet| pmt_set.contains(&packet.pid)) {
+        if let Some(section) = section(packet, 0x02) {
+            programs.entry(packet.pid).or_insert_with(|| parse_pmt(section));
+        }
+    }
+    if programs.is_empty() {
+        bail!(HlsError::Demux("Pas de PMT".to_owned()));
+    }
+
+    let find = |streams: &[(u8, u16)], stream_type| {
+        streams
+            .iter()
+            .find(|stream| stream.0 == stream_type && stream.1 != NULL_PID)
+            .map(|stream| stream.1)
+    };
+    let Some((streams, audio_pid)) = pmt_pids
+        .iter()
+        .filter_map(|pid| programs.get(pid))
+        .find_map(|streams| Some((streams, find(streams, STREAM_TYPE_ADTS)?)))
+    else {
+        bail!(HlsError::Demux("Pas de flux AAC dans les programmes".to_owned()));
+    };
+
+    Ok(Demuxed {
+        audio: reassemble(&packets, audio_pid),
+        metadata: find(streams, STREAM_TYPE_ID3).map(|pid| reassemble(&packets, pid)).unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Un paquet TS rempli d'octets de bourrage dans son champ d'adaptation
+    fn packet(pid: u16, start: bool, continuity: u8, payload: &[u8]) -> Vec<u8> {
+        assert!(payload.len() <= PACKET_LEN - 4);
+        let mut packet = vec![SYNC, (start as u8) << 6 | (pid >> 8) as u8, pid as u8];
+        let stuffing = PACKET_LEN - 4 - payload.len();
+        match stuffing {
+            0 => packet.push(0x10 | continuity),
+            _ => {
+                packet.push(0x30 | continuity);
+                packet.push((stuffing - 1) as u8);
+                if stuffing > 1 {
+                    packet.push(0x00);
+                    packet.resize(packet.len() + stuffing - 2, 0xFF);
+                }
+            }
+        }
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    // Une section PSI avec un CRC fictif
+    fn psi(table_id: u8, body: &[u8]) -> Vec<u8> {
+        let len = 5 + body.len() + 4;
+        let mut section = vec![0x00, table_id, 0xB0 | (len >> 8) as u8, len as u8, 0x00, 0x01, 0xC1, 0x00, 0x00];
+        section.extend_from_slice(body);
+        section.extend_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        section
+    }
+
+    fn pat(programs: &[(u16, u16)]) -> Vec<u8> {
+        let body = programs
+            .iter()
+            .flat_map(|(program, pid)| [(program >> 8) as u8, *program as u8, 0xE0 | (pid >> 8) as u8, *pid as u8])
+            .collect::<Vec<_>>();
+        packet(PAT_PID, true, 0, &psi(0x00, &body))
+    }
+
+    fn pmt(pid: u16, streams: &[(u8, u16)]) -> Vec<u8> {
+        let mut body = vec![0xE1, 0x00, 0xF0, 0x00]; // PCR_PID, program_info_length
+        for (stream_type, pid) in streams {
+            body.extend_from_slice(&[*stream_type, 0xE0 | (pid >> 8) as u8, *pid as u8, 0xF0, 0x00]);
+        }
+        packet(pid, true, 0, &psi(0x02, &body))
+    }
+
+    fn pes(stream_id: u8, pts: u64, data: &[u8]) -> Vec<u8> {
+        let len = 3 + 5 + data.len();
+        let mut pes = vec![0x00, 0x00, 0x01, stream_id, (len >> 8) as u8, len as u8, 0x80, 0x80, 0x05];
+        pes.extend_from_slice(&[
+            0x21 | ((pts >> 29) & 0x0E) as u8,
+            (pts >> 22) as u8,
+            0x01 | ((pts >> 14) & 0xFE) as u8,
+            (pts >> 7) as u8,
+            0x01 | ((pts << 1) & 0xFE) as u8,
+        ]);
+        pes.extend_from_slice(data);
+        pes
+    }
+
+    #[test]
+    fn reassembly() {
+        let audio = (0..=255).cycle().take(300).collect::<Vec<u8>>();
+        let first = pes(0xC0, 900_000, &audio);
+        let second = pes(0xC0, 901_920, b"fin");
+
+        let mut segment = b"\x00\x47garbage".to_vec();
+        segment.extend(pat(&[(1, 0x1000)]));
+        segment.extend(pmt(0x1000, &[(STREAM_TYPE_ADTS, 0x101)]));
+        segment.extend(packet(0x101, true, 0, &first[..184]));
+        segment.extend(packet(0x101, false, 1, &first[184..]));
+        segment.extend(packet(0x101, false, 1, &first[184..])); // Paquet répété
+        segment.extend(packet(0x101, true, 3, &second)); // Paquet perdu
+        segment.extend(packet(NULL_PID, false, 0, &[]));
+
+        let pes = demux(&segment).unwrap().audio;
+        assert_eq!(
+            pes,
+            [
+                Pes {
+                    pts: Some(900_000),
+                    data: audio.into()
+                },
+                Pes {
+                    pts: Some(901_920),
+                    data: Bytes::from_static(b"fin")
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn programs() {
+        // La PMT précède la PAT, et le premier programme n'a que de la vidéo
+        let mut segment = pmt(0x1001, &[(0x1B, 0x200), (STREAM_TYPE_ID3, 0x202), (STREAM_TYPE_ADTS, 0x201)]);
+        segment.extend(packet(0x201, true, 0, &pes(0xC0, 0, b"aac")));
+        segment.extend(packet(0x202, true, 0, &pes(0xBD, 90_000, b"ID3")));
+        segment.extend(pat(&[(0, 0x10), (1, 0x1000), (2, 0x1001)]));
+        segment.extend(pmt(0x1000, &[(0x1B, 0x100)]));
+        segment.extend(packet(0x100, true, 0, &pes(0xE0, 0, b"h264")));
+
+        let demuxed = demux(&segment).unwrap();
+        assert_eq!(demuxed.audio.len(), 1);
+        assert_eq!(demuxed.audio[0].data, &b"aac"[..]);
+        assert_eq!(
+            demuxed.metadata,
+            [Pes {
+                pts: Some(90_000),
+                data: Bytes::from_static(b"ID3")
+            }]
+        );
+
+        let e = demux(&pmt(0x1000, &[(STREAM_TYPE_ADTS, 0x101)])).unwrap_err();
+        assert_eq!(e.to_string(), "Pas de PAT");
+        let e = demux(&pat(&[(1, 0x1000)])).unwrap_err();
+        assert_eq!(e.to_string(), "Pas de PMT");
+        let mut segment = pat(&[(1, 0x1000)]);
+        segment.extend(pmt(0x1000, &[(0x1B, 0x100)]));
+        assert!(matches!(
+            demux(&segment).unwrap_err().downcast_ref::<HlsError>(),
+            Some(HlsError::Demux(_))
+        ));
+        assert_eq!(demux(b"garbage").unwrap_err().to_string(), "Pas de paquet TS");
+    }
+}
@@ -0,0 +1,117 @@
+use hls_m3u8::tags::VariantStream;
+
+// AAC-LC, puis HE-AAC et HE-AACv2
+pub(crate) const CODECS: [&str; 3] = ["mp4a.40.2", "mp4a.40.5", "mp4a.40.29"];
+
+// Sélection du flux parmi les variantes de la MasterPlaylist
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum VariantPolicy {
+    #[default]
+    Highest,
+    Lowest,
+    // Le «bitrate» le plus élevé qui ne dépasse pas la limite (en bits par seconde)
+    MaxBandwidth(u64),
+}
+
+// Rang de la variante selon la liste des codecs préférés, les variantes audio seulement d'abord.
+// Une variante sans CODECS est acceptée en dernier recours
+fn rank(vs: &VariantStream, codecs: &[String]) -> Option<(usize, bool)> {
+    match vs.codecs() {
+        Some(vs_codecs) => {
+            let video = vs_codecs.iter().any(|c| !c.starts_with("mp4a."));
+            vs_codecs
+                .iter()
+                .filter_map(|c| codecs.iter().position(|codec| codec == c))
+                .min()
+                .map(|rank| (rank, video))
+        }
+        None => Some((codecs.len(), false)),
+    }
+}
+
+// Les variantes audio ayant le meilleur rang, en ordre croissant de «bitrate»
+pub(crate) fn candidates<'a, 'b>(variants: &'b [VariantStream<'a>], codecs: &[String], policy: VariantPolicy) -> Vec<&'b VariantStream<'a>> {
+    let mut audio = variants
+        .iter()
+        .filter(|vs| matches!(vs, VariantStream::ExtXStreamInf { .. }))
+        .filter_map(|vs| rank(vs, codecs).map(|rank| (rank, vs)))
+        .collect::<Vec<_>>();
+
+    // La limite a préséance sur les codecs préférés, tant qu'une variante la respecte
+    if let VariantPolicy::MaxBandwidth(max) = policy
+        && audio.iter().any(|(_, vs)| vs.bandwidth() <= max)
+    {
+        audio.retain(|(_, vs)| vs.bandwidth() <= max);
+    }
+
+    let best = match audio.iter().map(|(rank, _)| *rank).min() {
+        Some(best) => best,
+        None => return Vec::new(),
+    };
+    let mut candidates = audio.into_iter().filter(|(rank, _)| *rank == best).map(|(_, vs)| vs).collect::<Vec<_>>();
+    candidates.sort_by_key(|vs| vs.bandwidth());
+    candidates
+}
+
+pub(crate) fn select<'a, 'b>(candidates: &[&'b VariantStream<'a>], policy: VariantPolicy) -> Option<&'b VariantStream<'a>> {
+    match policy {
+        VariantPolicy::Highest => candidates.last().copied(),
+        VariantPolicy::Lowest => candidates.first().copied(),
+        // Faute de mieux, le «bitrate» le plus faible
+        VariantPolicy::MaxBandwidth(max) => candidates.iter().rev().find(|vs| vs.bandwidth() <= max).or(candidates.first()).copied(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hls_m3u8::MasterPlaylist;
+
+    const MASTER: &str = concat!(
+        "#EXTM3U\n",
+        "#EXT-X-STREAM-INF:BANDWIDTH=32000,CODECS=\"mp4a.40.29\"\n",
+        "he2_32.m3u8\n",
+        "#EXT-X-STREAM-INF:BANDWIDTH=48000,CODECS=\"mp4a.40.5\"\n",
+        "he_48.m3u8\n",
+        "#EXT-X-STREAM-INF:BANDWIDTH=96000,CODECS=\"mp4a.40.2\"\n",
+        "lc_96.m3u8\n",
+        "#EXT-X-STREAM-INF:BANDWIDTH=192000,CODECS=\"mp4a.40.2\"\n",
+        "lc_192.m3u8\n",
+        "#EXT-X-STREAM-INF:BANDWIDTH=800000,CODECS=\"avc1.4d401f,mp4a.40.2\"\n",
+        "video_800.m3u8\n",
+        "#EXT-X-STREAM-INF:BANDWIDTH=64000,CODECS=\"mp4a.40.34\"\n",
+        "mp3_64.m3u8\n",
+    );
+
+    fn uri<'a>(vs: Option<&'a VariantStream>) -> &'a str {
+        match vs {
+            Some(VariantStream::ExtXStreamInf { uri, .. }) => uri,
+            _ => "",
+        }
+    }
+
+    fn pick(codecs: &[&str], policy: VariantPolicy) -> String {
+        let master = MasterPlaylist::try_from(MASTER).unwrap();
+        let codecs = codecs.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        let candidates = candidates(&master.variant_streams, &codecs, policy);
+        uri(select(&candidates, policy)).to_owned()
+    }
+
+    #[test]
+    fn policy() {
+        assert_eq!(pick(&CODECS, VariantPolicy::Highest), "lc_192.m3u8");
+        assert_eq!(pick(&CODECS, VariantPolicy::Lowest), "lc_96.m3u8");
+        assert_eq!(pick(&CODECS, VariantPolicy::MaxBandwidth(100_000)), "lc_96.m3u8");
+        assert_eq!(pick(&CODECS, VariantPolicy::MaxBandwidth(50_000)), "he_48.m3u8");
+        assert_eq!(pick(&CODECS, VariantPolicy::MaxBandwidth(1_000)), "lc_96.m3u8");
+        assert_eq!(pick(&["mp4a.40.29", "mp4a.40.5"], VariantPolicy::Highest), "he2_32.m3u8");
+        assert_eq!(pick(&["mp4a.40.5"], VariantPolicy::Highest), "he_48.m3u8");
+        assert_eq!(pick(&["opus"], VariantPolicy::Highest), "");
+
+        let master = MASTER.replace("CODECS=\"mp4a.40.2\"", "CODECS=\"mp4a.40.2,avc1.4d401f\"");
+        let master = MasterPlaylist::try_from(master.as_str()).unwrap();
+        let codecs = ["mp4a.40.2".to_owned()];
+        let candidates = candidates(&master.variant_streams, &codecs, VariantPolicy::Lowest);
+        assert_eq!(uri(select(&candidates, VariantPolicy::Lowest)), "lc_96.m3u8");
+    }
+}
@@ -0,0 +1,66 @@
+use bytes::Bytes;
+
+// Les segments reçus, bout à bout sans être copiés: chacun avec sa position de départ
+#[derive(Default)]
+pub(crate) struct Chunks {
+    chunks: Vec<(u64, Bytes)>,
+    len: u64,
+}
+
+impl Chunks {
+    pub(crate) fn new(data: Bytes) -> Self {
+        let mut chunks = Self::default();
+        chunks.push(data);
+        chunks
+    }
+
+    pub(crate) fn push(&mut self, data: Bytes) {
+        if !data.is_empty() {
+            let len = data.len() as u64;
+            self.chunks.push((self.len, data));
+            self.len += len;
+        }
+    }
+
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    // Copie dans buf à partir de la position, au travers des segments
+    pub(crate) fn read_at(&self, pos: u64, buf: &mut [u8]) -> usize {
+        let mut i = self.chunks.partition_point(|(start, data)| start + data.len() as u64 <= pos);
+        let mut n = 0;
+        while n < buf.len()
+            && let Some((start, data)) = self.chunks.get(i)
+        {
+            let offset = (pos + n as u64 - start) as usize;
+            let len = (data.len() - offset).min(buf.len() - n);
+            buf[n..n + len].copy_from_slice(&data[offset..offset + len]);
+            n += len;
+            i += 1;
+        }
+        n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_at() {
+        let mut chunks = Chunks::new(Bytes::from_static(b"0123"));
+        chunks.push(Bytes::new());
+        chunks.push(Bytes::from_static(b"45"));
+        chunks.push(Bytes::from_static(b"6789"));
+        assert_eq!(chunks.len(), 10);
+
+        let mut buf = [0; 5];
+        assert_eq!(chunks.read_at(3, &mut buf), 5);
+        assert_eq!(&buf, b"34567");
+        assert_eq!(chunks.read_at(8, &mut buf), 2);
+        assert_eq!(&buf[..2], b"89");
+        assert_eq!(chunks.read_at(10, &mut buf), 0);
+        assert_eq!(chunks.read_at(42, &mut buf), 0);
+    }
+}
@@ -1,18 +1,30 @@
+mod chunks;
 #[cfg(not(feature = "throttling"))]
 mod rxcursor;
 #[cfg(feature = "throttling")]
 mod rxcursor2;
 use std::fs::File;
 use std::io::Read;
+use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::Result;
+pub use hls_handler::{GapPolicy, HlsConfig, HlsError, HlsStats, Id3Frame, LiveStart, Metadata, Rendition, RenditionPolicy, Stats, VariantPolicy};
 use rodio::cpal::traits::HostTrait;
-use rodio::{Decoder, DeviceTrait, cpal};
+use rodio::{DeviceTrait, cpal};
 pub use rodio::{OutputStream, OutputStreamBuilder, Sink};
-use rxcursor::RxCursor;
+use rxcursor::decode;
 
 pub fn start(url: &str) -> Result<(Sink, OutputStream)> {
-    let rx = hls_handler::start(url)?;
+    start_with(url, HlsConfig::builder().build()?)
+}
+
+// Un épisode repris à la position
+pub fn start_at(url: &str, position: Duration) -> Result<(Sink, OutputStream)> {
+    start_with(url, HlsConfig::builder().start_position(position).build()?)
+}
+
+pub fn start_with(url: &str, config: HlsConfig) -> Result<(Sink, OutputStream)> {
+    let (rx, handle) = hls_handler::start_with(url, config)?;
 
     let mut cfg = std::env::current_exe()?;
     cfg.set_extension("cfg");
@@ -35,8 +47,7 @@ pub fn start(url: &str) -> Result<(Sink, OutputStream)> {
     };
 
     let sink = Sink::connect_new(output_stream.mixer());
-    let source = Decoder::new(RxCursor::new(rx)?).context("Échec: création de Decoder")?;
-    sink.append(source);
+    sink.append(decode(rx, handle)?);
 
     Ok((sink, output_stream))
 }
@@ -44,7 +55,7 @@ pub fn start(url: &str) -> Result<(Sink, OutputStream)> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::{thread, time::Duration};
+    use std::thread;
 
     #[test]
     fn ohdio() {
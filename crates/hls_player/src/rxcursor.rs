@@ -1,57 +1,90 @@
-use anyhow::Result;
+use crate::chunks::Chunks;
+use anyhow::{Context, Result};
+use hls_handler::{HlsHandle, Segment};
+use rodio::queue::{SourcesQueueInput, SourcesQueueOutput, queue};
+use rodio::{Decoder, Source};
 use std::io::{Error, ErrorKind, Read, Seek, SeekFrom};
 use std::sync::mpsc::Receiver;
-use std::sync::{
-    Arc, Mutex,
-    atomic::{AtomicBool, Ordering},
-};
+use std::sync::{Arc, Mutex};
 use std::thread;
 
+// Annule le téléchargement lorsque le dernier RxCursor est libéré
+struct Guard(HlsHandle);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.0.cancel(); // Ne pas attendre la fin du téléchargement en cours
+    }
+}
+
+// Les segments d'une même époque, entre deux discontinuités
 pub struct RxCursor {
-    inner: Arc<Mutex<Vec<u8>>>,
+    inner: Arc<Mutex<Chunks>>,
     pos: u64,
-    stop_signal: Arc<AtomicBool>,
+    _guard: Arc<Guard>,
 }
 
-impl RxCursor {
-    pub fn new(rx: Receiver<Result<Vec<u8>>>) -> Result<Self> {
-        let mut buf: Vec<u8> = Vec::new();
-        let mut stream = rx.recv()??; // Wait for first TS packet
-        buf.append(&mut stream);
-        let inner = Arc::new(Mutex::new(buf));
-        let inner2 = inner.clone();
-        let stop_signal = Arc::new(AtomicBool::new(false));
-        let stop_signal2 = stop_signal.clone();
+// Un Decoder par époque: le flux est sondé de nouveau après chaque discontinuité
+pub fn decode(rx: Receiver<Result<Segment>>, handle: HlsHandle) -> Result<SourcesQueueOutput> {
+    // Un segment vide a été rejeté par hls_handler: sa discontinuité est reportée au suivant
+    let mut segment = rx.recv()??; // Wait for first segment
+    while segment.data.is_empty() {
+        segment = rx.recv()??;
+    }
+    let mut discontinuity = false;
+    let guard = Arc::new(Guard(handle));
+    let (input, output) = queue(false);
+    let mut inner = epoch(&input, guard.clone(), segment)?;
+    let guard = Arc::downgrade(&guard);
 
-        thread::spawn(move || {
-            while !stop_signal2.load(Ordering::Relaxed) {
-                match rx.recv() {
-                    Ok(message) => {
-                        match message {
-                            Ok(mut stream) => inner2.lock().expect("Poisoned lock").append(&mut stream),
-                            Err(e) => return eprintln!("{e:?}"),
-                        };
-                    }
-                    Err(_) => return, // tx was dropped
+    thread::spawn(move || {
+        loop {
+            match rx.recv() {
+                Ok(message) => {
+                    match message {
+                        Ok(segment) if segment.data.is_empty() => discontinuity |= segment.discontinuity,
+                        Ok(segment) if segment.discontinuity || discontinuity => {
+                            discontinuity = false;
+                            let Some(guard) = guard.upgrade() else {
+                                return; // RxCursor was dropped
+                            };
+                            inner = match epoch(&input, guard, segment) {
+                                Ok(inner) => inner,
+                                Err(e) => return eprintln!("{e:?}"),
+                            };
+                        }
+                        Ok(segment) => inner.lock().expect("Poisoned lock").push(segment.data),
+                        Err(e) => return eprintln!("{e:?}"),
+                    };
                 }
+                Err(_) => return, // tx was dropped
             }
-        });
+        }
+    });
 
-        Ok(Self { inner, pos: 0, stop_signal })
-    }
+    Ok(output)
 }
 
-impl Drop for RxCursor {
-    fn drop(&mut self) {
-        self.stop_signal.store(true, Ordering::Relaxed);
+fn epoch(input: &SourcesQueueInput, guard: Arc<Guard>, segment: Segment) -> Result<Arc<Mutex<Chunks>>> {
+    let inner = Arc::new(Mutex::new(Chunks::new(segment.data.clone())));
+    let cursor = RxCursor {
+        inner: inner.clone(),
+        pos: 0,
+        _guard: guard,
+    };
+    let source = Decoder::new(cursor).with_context(|| format!("Échec: création de Decoder au segment {}", segment.sequence))?;
+    // Le premier segment d'un flux commencé à une position est retranché jusqu'à celle-ci
+    match segment.trim.is_zero() {
+        true => input.append(source),
+        false => input.append(source.skip_duration(segment.trim)),
     }
+    Ok(inner)
 }
 
 impl Read for RxCursor {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
         let inner = self.inner.lock().expect("Poisoned lock");
-        let len = self.pos.min(inner.len() as u64);
-        let n = Read::read(&mut &inner[(len as usize)..], buf)?;
+        let n = inner.read_at(self.pos, buf);
         self.pos += n as u64;
         Ok(n)
     }
@@ -65,7 +98,7 @@ impl Seek for RxCursor {
                 return Ok(n);
             }
 
-            SeekFrom::End(n) => ((self.inner.lock().expect("Poisoned lock")).len() as u64, n),
+            SeekFrom::End(n) => (self.inner.lock().expect("Poisoned lock").len(), n),
             SeekFrom::Current(n) => (self.pos, n),
         };
         let new_pos = if offset >= 0 {
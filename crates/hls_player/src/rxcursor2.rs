@@ -1,5 +1,9 @@
 // RxCursor with download throttling
-use anyhow::Result;
+use crate::chunks::Chunks;
+use anyhow::{Context, Result};
+use hls_handler::{HlsHandle, Segment};
+use rodio::queue::{SourcesQueueInput, SourcesQueueOutput, queue};
+use rodio::{Decoder, Source};
 use std::io::{Error, ErrorKind, Read, Seek, SeekFrom};
 use std::sync::mpsc::Receiver;
 use std::sync::{
@@ -8,66 +12,94 @@ use std::sync::{
 };
 use std::{thread, time::Duration};
 
-const RESERVE: usize = 1_024_000;
+const RESERVE: u64 = 1_024_000;
 
+// Annule le téléchargement lorsque le dernier RxCursor est libéré
+struct Guard(HlsHandle);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        self.0.cancel(); // Ne pas attendre la fin du téléchargement en cours
+    }
+}
+
+// Les segments d'une même époque, entre deux discontinuités
 pub struct RxCursor {
-    inner: Arc<Mutex<Vec<u8>>>,
+    inner: Arc<Mutex<Chunks>>,
     pos: u64,
-    stop_signal: Arc<AtomicBool>,
+    _guard: Arc<Guard>,
     download_signal: Arc<AtomicBool>,
 }
 
-impl RxCursor {
-    pub fn new(rx: Receiver<Result<Vec<u8>>>) -> Result<Self> {
-        let mut buf: Vec<u8> = Vec::new();
-        let mut stream = rx.recv()??; // Wait for first TS packet
-        buf.append(&mut stream);
-        let inner = Arc::new(Mutex::new(buf));
-        let inner2 = inner.clone();
-        let stop_signal = Arc::new(AtomicBool::new(false));
-        let stop_signal2 = stop_signal.clone();
-        let download_signal = Arc::new(AtomicBool::new(true));
-        let download_signal2 = download_signal.clone();
+// Un Decoder par époque: le flux est sondé de nouveau après chaque discontinuité
+pub fn decode(rx: Receiver<Result<Segment>>, handle: HlsHandle) -> Result<SourcesQueueOutput> {
+    // Un segment vide a été rejeté par hls_handler: sa discontinuité est reportée au suivant
+    let mut segment = rx.recv()??; // Wait for first segment
+    while segment.data.is_empty() {
+        segment = rx.recv()??;
+    }
+    let mut discontinuity = false;
+    let guard = Arc::new(Guard(handle));
+    let download_signal = Arc::new(AtomicBool::new(true));
+    let (input, output) = queue(false);
+    let mut inner = epoch(&input, guard.clone(), &download_signal, segment)?;
+    let guard = Arc::downgrade(&guard);
 
-        thread::spawn(move || {
-            while !stop_signal2.load(Ordering::Relaxed) {
-                if download_signal2.load(Ordering::Relaxed) {
-                    match rx.recv() {
-                        Ok(message) => {
-                            match message {
-                                Ok(mut stream) => inner2.lock().expect("Poisoned lock").append(&mut stream),
-                                Err(e) => return eprintln!("{e:?}"),
-                            };
-                        }
-                        Err(_) => return, // tx was dropped
+    thread::spawn(move || {
+        while guard.strong_count() > 0 {
+            if download_signal.load(Ordering::Relaxed) {
+                match rx.recv() {
+                    Ok(message) => {
+                        match message {
+                            Ok(segment) if segment.data.is_empty() => discontinuity |= segment.discontinuity,
+                            Ok(segment) if segment.discontinuity || discontinuity => {
+                                discontinuity = false;
+                                let Some(guard) = guard.upgrade() else {
+                                    return; // RxCursor was dropped
+                                };
+                                inner = match epoch(&input, guard, &download_signal, segment) {
+                                    Ok(inner) => inner,
+                                    Err(e) => return eprintln!("{e:?}"),
+                                };
+                            }
+                            Ok(segment) => inner.lock().expect("Poisoned lock").push(segment.data),
+                            Err(e) => return eprintln!("{e:?}"),
+                        };
                     }
+                    Err(_) => return, // tx was dropped
                 }
-                thread::sleep(Duration::from_secs(1));
             }
-        });
+            thread::sleep(Duration::from_secs(1));
+        }
+    });
 
-        Ok(Self {
-            inner,
-            pos: 0,
-            stop_signal,
-            download_signal,
-        })
-    }
+    Ok(output)
 }
 
-impl Drop for RxCursor {
-    fn drop(&mut self) {
-        self.stop_signal.store(true, Ordering::Relaxed);
+fn epoch(input: &SourcesQueueInput, guard: Arc<Guard>, download_signal: &Arc<AtomicBool>, segment: Segment) -> Result<Arc<Mutex<Chunks>>> {
+    let inner = Arc::new(Mutex::new(Chunks::new(segment.data.clone())));
+    let cursor = RxCursor {
+        inner: inner.clone(),
+        pos: 0,
+        _guard: guard,
+        download_signal: download_signal.clone(),
+    };
+    let source = Decoder::new(cursor).with_context(|| format!("Échec: création de Decoder au segment {}", segment.sequence))?;
+    // Le premier segment d'un flux commencé à une position est retranché jusqu'à celle-ci
+    match segment.trim.is_zero() {
+        true => input.append(source),
+        false => input.append(source.skip_duration(segment.trim)),
     }
+    Ok(inner)
 }
 
 impl Read for RxCursor {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
         let inner = self.inner.lock().expect("Poisoned lock");
-        let len = self.pos.min(inner.len() as u64);
-        let n = Read::read(&mut &inner[(len as usize)..], buf)?;
+        let n = inner.read_at(self.pos, buf);
         self.pos += n as u64;
-        self.download_signal.store(inner.len() - (self.pos as usize) < RESERVE, Ordering::Relaxed);
+        self.download_signal
+            .store(inner.len().saturating_sub(self.pos) < RESERVE, Ordering::Relaxed);
         Ok(n)
     }
 }
@@ -80,7 +112,7 @@ impl Seek for RxCursor {
                 return Ok(n);
             }
 
-            SeekFrom::End(n) => ((self.inner.lock().expect("Poisoned lock")).len() as u64, n),
+            SeekFrom::End(n) => (self.inner.lock().expect("Poisoned lock").len(), n),
             SeekFrom::Current(n) => (self.pos, n),
         };
         let new_pos = if offset >= 0 {
@@ -1,8 +1,9 @@
 mod handler {
-    use hls_player::{OutputStream, Sink};
+    use hls_player::{HlsConfig, HlsError, HlsStats, LiveStart, Metadata, OutputStream, Rendition, Sink};
     use media::{Episode, get_episodes};
     use serde::{Deserialize, Serialize};
     use std::cell::RefCell;
+    use std::sync::mpsc::{Receiver, Sender, channel};
     use std::thread_local;
 
     #[derive(Serialize, Clone, PartialEq)]
@@ -23,6 +24,10 @@ mod handler {
         message: String,
         en_lecture: Episode,
         en_lecture_prog: usize,
+        en_ondes: String, /* titre ID3 du direct */
+        diagnostic: String,
+        pistes: Vec<String>, /* pistes audio (EXT-X-MEDIA) de la variante */
+        piste: String,
     }
 
     #[derive(Deserialize, PartialEq)]
@@ -47,6 +52,8 @@ mod handler {
     thread_local! {
         static SINK: RefCell<Option<Sink>> = const { RefCell::new(None) };
         static OUTPUT_STREAM: RefCell<Option<OutputStream>> = const { RefCell::new(None) };
+        static METADATA: RefCell<Option<Receiver<Metadata>>> = const { RefCell::new(None) };
+        static STATS: RefCell<Option<HlsStats>> = const { RefCell::new(None) };
         static STATE: RefCell<State> = RefCell::new(State {
             player: PlayerState::Stopped,
             volume: 2,
@@ -58,6 +65,10 @@ mod handler {
             message: String::default(),
             en_lecture: Episode::default(),
             en_lecture_prog: 0,
+            en_ondes: String::default(),
+            diagnostic: String::default(),
+            pistes: Vec::new(),
+            piste: String::default(),
         });
         static PAGES: RefCell<Vec<Vec<Episode>>> = RefCell::new(Vec::new());
     }
@@ -70,10 +81,12 @@ mod handler {
     use std::time::Duration;
 
     const TIME_OUT: u64 = 30;
+    const CACHE_DIR: &str = "odieux_cache"; // Partagé avec hls2file
+    const MAX_LATENCY: u64 = 60; // «En direct» se resynchronise au-delà de ce retard
     const URL_VALIDEUR_OD: &str = "https://services.radio-canada.ca/media/validation/v2/?appCode=medianet&connectionType=hd&deviceType=ipad&idMedia={}&multibitrate=true&output=json&tech=hls&manifestVersion=2";
     const URL_VALIDEUR_LIVE: &str = "https://services.radio-canada.ca/media/validation/v2/?appCode=medianetlive&connectionType=hd&deviceType=ipad&idMedia=cbvx&multibitrate=true&output=json&tech=hls&manifestVersion=2";
 
-    async fn start_player(media_id: Option<&str>) -> Result<(Sink, OutputStream)> {
+    async fn start_player(media_id: Option<&str>, metadata: Sender<Metadata>, stats: HlsStats) -> Result<(Sink, OutputStream)> {
         let url = match media_id {
             Some(media_id) => URL_VALIDEUR_OD.replace("{}", &media_id),
             None => URL_VALIDEUR_LIVE.to_owned(),
@@ -81,31 +94,116 @@ mod handler {
         let client = Client::builder().timeout(Duration::from_secs(TIME_OUT)).build()?;
         let response = client.get(&url).send().await?.text().await?;
         let value: Value = serde_json::from_str(&response)?;
-        hls_player::start(value["url"].as_str().unwrap_or_default())
+        let config = HlsConfig::builder()
+            .metadata(metadata)
+            .stats(stats)
+            .live_start(LiveStart::HoldBack)
+            .max_latency(Some(Duration::from_secs(MAX_LATENCY)));
+        // Le cache de l'utilisateur, s'il en a un
+        let config = match dirs::cache_dir() {
+            Some(dir) => config.cache_dir(dir.join(CACHE_DIR)),
+            None => config,
+        };
+        hls_player::start_with(value["url"].as_str().unwrap_or_default(), config.build()?)
     }
 
     fn command_stop() {
         OUTPUT_STREAM.set(None);
         SINK.set(None);
+        METADATA.set(None);
+        STATS.set(None);
         STATE.with_borrow_mut(|state| {
             state.player = PlayerState::Stopped;
             state.en_lecture = Episode::default();
+            state.en_ondes = String::default();
+            state.diagnostic = String::default();
+            state.pistes = Vec::new();
+            state.piste = String::default();
         });
     }
 
-    async fn command_start(episode: Episode) {
-        command_stop();
-        let result = if episode.titre == "En direct" {
-            start_player(None).await
+    async fn start_episode(episode: &Episode, metadata: Sender<Metadata>, stats: HlsStats) -> Result<(Sink, OutputStream)> {
+        if episode.titre == "En direct" {
+            start_player(None, metadata, stats).await
         } else if episode.media_id.is_empty() {
             Err(anyhow!("Aucune musique diffusée disponible"))
         } else {
-            start_player(Some(&episode.media_id)).await
+            start_player(Some(&episode.media_id), metadata, stats).await
+        }
+    }
+
+    // Le titre le plus récent annoncé dans les métadonnées du flux
+    fn update_en_ondes() {
+        let titre = METADATA.with_borrow(|rx| rx.as_ref().and_then(|rx| rx.try_iter().filter_map(|metadata| metadata.title()).last()));
+        if let Some(titre) = titre {
+            STATE.with_borrow_mut(|state| state.en_ondes = titre);
+        }
+    }
+
+    // Débit de la variante, reprises et avance sur le direct. Le téléchargement est en retard
+    // lorsqu'un segment prend plus de temps à obtenir que la moitié de sa durée
+    fn update_diagnostic() {
+        let Some(stats) = STATS.with_borrow(|stats| stats.as_ref().map(HlsStats::snapshot)) else {
+            return;
+        };
+        let mut diagnostic = format!("{} kbps, {:.1} Mo", stats.bandwidth / 1000, stats.bytes as f64 / 1_000_000.0);
+        if stats.retries > 0 {
+            diagnostic += &format!(", {} reprises", stats.retries);
+        }
+        if let Some(live_edge) = stats.live_edge {
+            diagnostic += &format!(", {} s du direct", live_edge.as_secs());
+        }
+        if let Some(download_time) = stats.download_time
+            && stats.segments > 0
+            && download_time > stats.total_duration / stats.segments as u32 / 2
+        {
+            diagnostic += ", mise en mémoire tampon...";
+        }
+        STATE.with_borrow_mut(|state| state.diagnostic = diagnostic);
+    }
+
+    // «Nom (langue)» de chaque piste audio et celle en lecture
+    fn update_pistes() {
+        let Some(stats) = STATS.with_borrow(|stats| stats.as_ref().map(HlsStats::snapshot)) else {
+            return;
         };
+        let piste = |rendition: &Rendition| match &rendition.language {
+            Some(language) => format!("{} ({language})", rendition.name),
+            None => rendition.name.clone(),
+        };
+        STATE.with_borrow_mut(|state| {
+            state.pistes = stats.renditions.iter().map(piste).collect();
+            state.piste = stats.rendition.as_ref().map(piste).unwrap_or_default();
+        });
+    }
+
+    // Un message clair selon la catégorie de l'erreur HLS, sinon la chaîne des erreurs
+    fn error_message(e: &anyhow::Error) -> String {
+        match e.downcast_ref::<HlsError>() {
+            Some(HlsError::NoSuitableVariant(_)) => "Aucun flux audio compatible".to_owned(),
+            Some(HlsError::HttpStatus(status)) if status.is_client_error() => format!("Épisode non disponible ({status})"),
+            Some(hls_error) if hls_error.is_retryable() => "Service momentanément indisponible, réessayez plus tard".to_owned(),
+            _ => format!("{e:#}"),
+        }
+    }
+
+    async fn command_start(episode: Episode) {
+        command_stop();
+        let (tx, rx) = channel();
+        let stats = HlsStats::default();
+        let mut result = start_episode(&episode, tx.clone(), stats.clone()).await;
+        if let Err(e) = &result
+            && e.downcast_ref::<HlsError>().is_some_and(HlsError::is_retryable)
+        {
+            eprintln!("{e:#}");
+            result = start_episode(&episode, tx, stats.clone()).await; // Une seconde tentative
+        }
         match result {
             Ok((new_sink, new_os)) => {
                 SINK.set(Some(new_sink));
                 OUTPUT_STREAM.set(Some(new_os));
+                METADATA.set(Some(rx));
+                STATS.set(Some(stats));
                 STATE.with_borrow_mut(|state| {
                     state.player = PlayerState::Playing;
                     state.en_lecture = episode;
@@ -114,9 +212,8 @@ mod handler {
                 });
             }
             Err(e) => {
-                let message = format!("{e:#}");
-                eprintln!("{message}");
-                STATE.with_borrow_mut(|state| state.message = message);
+                eprintln!("{e:#}");
+                STATE.with_borrow_mut(|state| state.message = error_message(&e));
             }
         }
     }
@@ -127,6 +224,9 @@ mod handler {
         }
         match command {
             Command::State => {
+                update_en_ondes();
+                update_diagnostic();
+                update_pistes();
                 // Vérifier si la lecture s'est terminée
                 if STATE.with_borrow(|state| state.en_lecture != Episode::default()) && SINK.with_borrow(|sink| sink.as_ref().unwrap().empty()) {
                     if STATE.with_borrow(|state| state.en_lecture.titre == "En direct") {